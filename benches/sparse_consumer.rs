@@ -0,0 +1,105 @@
+//! Compares the throughput of skipping the values that no consumer is registered for, with
+//! fully scanning them. Run with `cargo bench --bench sparse_consumer`.
+
+use inc_json_rs::parser::{
+    buffer::Buffer,
+    json_path::{ConsumerAction, JsonPrimitive, ObjectConsumer, PathContext, UnknownConsumer},
+    parser::Parser,
+};
+use std::{
+    borrow::BorrowMut,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+const RECORDS: usize = 20_000;
+const CHUNK_SIZE: usize = 64 * 1024;
+const RUNS: usize = 3;
+
+static IDS_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Each record has one wanted field, the rest of it is ignored
+fn sparse_document() -> String {
+    let mut json = String::from("{\"metadata\": {\"version\": 3}, \"records\": [");
+    for i in 0..RECORDS {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str(&format!(
+            concat!(
+                "{{\"id\": {}, \"name\": \"record \\\"{}\\\"\", \"score\": {}.25e2, ",
+                "\"tags\": [\"a\", \"b\", \"c\", null, true], ",
+                "\"address\": {{\"street\": \"1 Long Road\", \"city\": \"Somewhere\", ",
+                "\"location\": [51.5072, -0.1276]}}, ",
+                "\"history\": [{{\"at\": \"2023-01-01T00:00:00Z\", \"event\": \"created\"}}, ",
+                "{{\"at\": \"2023-06-01T00:00:00Z\", \"event\": \"updated\"}}]}}"
+            ),
+            i, i, i
+        ));
+    }
+    json.push_str("]}");
+    return json;
+}
+
+fn json_path() -> ObjectConsumer {
+    return ObjectConsumer::new()
+        .array(
+            "records".to_string(),
+            UnknownConsumer::ObjectConsumer(
+                ObjectConsumer::new()
+                    .primitive("id".to_string(), |x: JsonPrimitive, _: &PathContext| {
+                        if let JsonPrimitive::Number(_) = x {
+                            IDS_SEEN.fetch_add(1, Ordering::Relaxed);
+                        }
+                        ConsumerAction::Continue
+                    })
+                    .clone(),
+            ),
+        )
+        .clone();
+}
+
+async fn parse(chunks: &[Vec<char>], skip_unregistered: bool) {
+    let mut buffer = Buffer::new();
+    for chunk in chunks {
+        buffer.add_data(chunk.clone()).await.unwrap();
+    }
+    buffer.eof().await;
+
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    Parser::new(json_path())
+        .skip_unregistered(skip_unregistered)
+        .parse(buffer_pinned)
+        .await
+        .unwrap();
+}
+
+fn main() {
+    let json = sparse_document();
+    let chars: Vec<char> = json.chars().collect();
+    let chunks: Vec<Vec<char>> = chars.chunks(CHUNK_SIZE).map(|x| x.to_vec()).collect();
+    let megabytes = json.len() as f64 / (1024.0 * 1024.0);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    for (name, skip_unregistered) in [("full scan", false), ("skip unregistered", true)] {
+        let mut best = f64::MAX;
+        for _ in 0..RUNS {
+            IDS_SEEN.store(0, Ordering::Relaxed);
+            let start = Instant::now();
+            runtime.block_on(parse(&chunks, skip_unregistered));
+            best = best.min(start.elapsed().as_secs_f64());
+            assert_eq!(IDS_SEEN.load(Ordering::Relaxed), RECORDS);
+        }
+
+        println!(
+            "{:>20}: {:.2} MiB in {:.3}s ({:.2} MiB/s)",
+            name,
+            megabytes,
+            best,
+            megabytes / best
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Field, Fields, GenericArgument, Ident, LitStr,
+    PathArguments, Type,
+};
+
+/// How a field is read, from its `#[inc_json(...)]` attributes
+struct FieldOptions {
+    /// The key of the field in the JSON object
+    key: String,
+    skip: bool,
+    flatten: bool,
+    stream: bool,
+}
+
+fn field_options(field: &Field) -> Result<FieldOptions, Error> {
+    let ident = field.ident.as_ref().unwrap();
+    let mut options = FieldOptions {
+        key: ident.to_string().trim_start_matches("r#").to_string(),
+        skip: false,
+        flatten: false,
+        stream: false,
+    };
+
+    for attr in field.attrs.iter().filter(|x| x.path().is_ident("inc_json")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                options.key = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("skip") {
+                options.skip = true;
+            } else if meta.path.is_ident("flatten") {
+                options.flatten = true;
+            } else if meta.path.is_ident("stream") {
+                options.stream = true;
+            } else {
+                return Err(meta.error("expected `rename`, `skip`, `flatten` or `stream`"));
+            }
+            return Ok(());
+        })?;
+    }
+
+    if [options.skip, options.flatten, options.stream]
+        .iter()
+        .filter(|x| **x)
+        .count()
+        > 1
+    {
+        return Err(Error::new_spanned(
+            ident,
+            "only one of `skip`, `flatten` and `stream` can be used on a field",
+        ));
+    }
+
+    return Ok(options);
+}
+
+/// Gets `T` from `Vec<T>`, the type of the members of a streamed field.
+fn vec_member_type(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(x) => x.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Vec" {
+        return None;
+    }
+
+    return match &segment.arguments {
+        PathArguments::AngleBracketed(x) => match x.args.first()? {
+            GenericArgument::Type(x) => Some(x),
+            _ => None,
+        },
+        _ => None,
+    };
+}
+
+/// Generates `FromJsonValue` and `IncJson` for a struct with named fields, and a parser for it
+/// named after the struct that has a callback setter for each streamed field.
+///
+/// Field attributes:
+/// - `#[inc_json(rename = "key")]` reads the field from a different key
+/// - `#[inc_json(skip)]` does not read the field, it is set to its default
+/// - `#[inc_json(flatten)]` reads the fields of the struct in the field from the same object
+/// - `#[inc_json(stream)]` passes the members of a `Vec` field to a callback on the parser as
+///   they are scanned, rather than collecting them
+#[proc_macro_derive(IncJson, attributes(inc_json))]
+pub fn derive_inc_json(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    return match expand(input) {
+        Ok(x) => x.into(),
+        Err(x) => x.to_compile_error().into(),
+    };
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let name = &input.ident;
+    let vis = &input.vis;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "IncJson cannot be derived for generic structs",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(x) => match &x.fields {
+            Fields::Named(x) => &x.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    name,
+                    "IncJson can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                name,
+                "IncJson can only be derived for structs",
+            ))
+        }
+    };
+
+    let krate = quote!(::inc_json_rs::parser);
+    let mut flattened = Vec::new();
+    let mut values = Vec::new();
+    let mut registrations = Vec::new();
+    let mut stream_setters = Vec::new();
+    for field in fields {
+        let options = field_options(field)?;
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let key = &options.key;
+
+        if options.skip {
+            values.push(quote!(#ident: ::core::default::Default::default()));
+        } else if options.flatten {
+            // Flattened fields are read before the other fields take their keys
+            flattened.push(quote! {
+                let #ident = <#ty as #krate::derive::FromJsonValue>::from_json_value(
+                    #krate::json_value::JsonValue::Object(fields.clone()),
+                )?;
+            });
+            values.push(quote!(#ident));
+            registrations.push(quote! {
+                <#ty as #krate::derive::IncJson>::register_fields(consumer);
+            });
+        } else if options.stream {
+            let member = match vec_member_type(ty) {
+                Some(x) => x,
+                None => return Err(Error::new_spanned(ty, "only `Vec` fields can be streamed")),
+            };
+            values.push(quote!(#ident: #krate::derive::take_streamed_field(&mut fields, #key)?));
+            registrations.push(quote! {
+                consumer.array(
+                    #key.to_string(),
+                    #krate::json_path::UnknownConsumer::ValueConsumer(#krate::derive::stream_member),
+                );
+            });
+
+            let setter = format_ident!("on_{}", ident.to_string().trim_start_matches("r#"));
+            let doc = format!("Called with each member of `{}` as it is scanned.", key);
+            stream_setters.push(quote! {
+                #[doc = #doc]
+                pub fn #setter(self, callback: impl FnMut(#member) + Send + 'static) -> Self {
+                    return Self {
+                        parser: self.parser.stream(#key, callback),
+                    };
+                }
+            });
+        } else {
+            values.push(quote!(#ident: #krate::derive::take_field(&mut fields, #key)?));
+            registrations.push(quote! {
+                <#ty as #krate::derive::FromJsonValue>::register_field(consumer, #key.to_string());
+            });
+        }
+    }
+
+    let parser = Ident::new(&format!("{}Parser", name), Span::call_site());
+    let parser_doc = format!(
+        "Parses a `{}` from the object at the root of the input.",
+        name
+    );
+    return Ok(quote! {
+        impl #krate::derive::FromJsonValue for #name {
+            fn from_json_value(
+                value: #krate::json_value::JsonValue,
+            ) -> ::core::result::Result<Self, &'static str> {
+                let mut fields = match value {
+                    #krate::json_value::JsonValue::Object(x) => x,
+                    _ => return ::core::result::Result::Err("Expected an object"),
+                };
+                #(#flattened)*
+                return ::core::result::Result::Ok(#name { #(#values),* });
+            }
+
+            fn register_field(consumer: &mut #krate::json_path::ObjectConsumer, key: String) {
+                #krate::derive::register_struct::<Self>(consumer, key);
+            }
+        }
+
+        impl #krate::derive::IncJson for #name {
+            fn register_fields(consumer: &mut #krate::json_path::ObjectConsumer) {
+                #(#registrations)*
+            }
+        }
+
+        #[doc = #parser_doc]
+        #vis struct #parser {
+            parser: #krate::derive::StructParser<#name>,
+        }
+
+        impl #parser {
+            pub fn new() -> Self {
+                return Self {
+                    parser: #krate::derive::StructParser::new(),
+                };
+            }
+
+            #(#stream_setters)*
+
+            pub async fn parse(
+                self,
+                buffer: &mut ::std::pin::Pin<::std::boxed::Box<&mut #krate::buffer::Buffer>>,
+            ) -> ::core::result::Result<#name, #krate::parser::ParseError> {
+                return self.parser.parse(buffer).await;
+            }
+        }
+    });
+}
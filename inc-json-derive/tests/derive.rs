@@ -0,0 +1,260 @@
+use inc_json_derive::IncJson;
+use inc_json_rs::parser::{buffer::Buffer, parser::ParseError};
+use std::{
+    borrow::BorrowMut,
+    sync::{Arc, Mutex},
+};
+
+#[derive(IncJson, Debug, PartialEq)]
+struct Owner {
+    id: i64,
+    name: Option<String>,
+}
+
+#[derive(IncJson, Debug, PartialEq)]
+struct Meta {
+    version: u32,
+    #[inc_json(rename = "updatedAt")]
+    updated_at: String,
+}
+
+#[derive(IncJson, Debug, PartialEq)]
+struct Item {
+    id: i64,
+    tags: Vec<String>,
+}
+
+#[derive(IncJson, Debug, PartialEq)]
+struct Feed {
+    #[inc_json(rename = "feedId")]
+    id: i64,
+    owner: Owner,
+    score: f64,
+    #[inc_json(skip)]
+    cached: bool,
+    #[inc_json(flatten)]
+    meta: Meta,
+    #[inc_json(stream)]
+    items: Vec<Item>,
+}
+
+#[derive(IncJson, Debug, PartialEq)]
+struct Page {
+    number: i64,
+    #[inc_json(stream)]
+    items: Vec<Item>,
+}
+
+#[derive(IncJson, Debug, PartialEq)]
+struct Ids {
+    #[inc_json(stream)]
+    ids: Vec<i64>,
+}
+
+#[derive(IncJson, Debug, PartialEq)]
+struct Listing {
+    #[inc_json(stream)]
+    items: Vec<Item>,
+    page: Page,
+    #[inc_json(flatten)]
+    ids: Ids,
+    owner: Option<Owner>,
+}
+
+async fn buffer_with_chunks(chunks: &[&str]) -> Buffer {
+    let mut buffer = Buffer::new();
+    for chunk in chunks {
+        buffer
+            .add_data(chunk.chars().collect::<Vec<char>>())
+            .await
+            .unwrap();
+    }
+    buffer.eof().await;
+    return buffer;
+}
+
+const FEED: [&str; 3] = [
+    r#"{"feedId": 7, "version": 2, "owner": {"id": 1, "other": []}, "score": 1, "#,
+    r#""items": [{"id": 1, "tags": ["a"]}, {"id": 2, "tags": []}], "#,
+    r#""updatedAt": "today", "cached": true}"#,
+];
+
+#[tokio::test]
+async fn test_parse_struct_streaming_fields() {
+    let items = Arc::new(Mutex::new(Vec::new()));
+    let items_clone = items.clone();
+
+    let mut buffer = buffer_with_chunks(&FEED).await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    let feed = FeedParser::new()
+        .on_items(move |x| items_clone.lock().unwrap().push(x))
+        .parse(buffer_pinned)
+        .await;
+
+    assert_eq!(
+        feed,
+        Ok(Feed {
+            id: 7,
+            owner: Owner { id: 1, name: None },
+            score: 1.0,
+            cached: false,
+            meta: Meta {
+                version: 2,
+                updated_at: "today".to_string()
+            },
+            items: vec![],
+        })
+    );
+    assert_eq!(
+        *items.lock().unwrap(),
+        vec![
+            Item {
+                id: 1,
+                tags: vec!["a".to_string()]
+            },
+            Item {
+                id: 2,
+                tags: vec![]
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_parse_struct_collects_fields_without_callback() {
+    let mut buffer = buffer_with_chunks(&FEED).await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    let feed = FeedParser::new().parse(buffer_pinned).await.unwrap();
+
+    assert_eq!(
+        feed.items,
+        vec![
+            Item {
+                id: 1,
+                tags: vec!["a".to_string()]
+            },
+            Item {
+                id: 2,
+                tags: vec![]
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_parse_struct_errors() {
+    let mut buffer = buffer_with_chunks(&[r#"{"id": 1, "tags": "a"}"#]).await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    assert_eq!(
+        ItemParser::new().parse(buffer_pinned).await,
+        Err(ParseError {
+            message: "Expected an array",
+            offset: 22
+        })
+    );
+
+    let mut buffer = buffer_with_chunks(&[r#"{"id": 1}"#]).await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    assert_eq!(
+        OwnerParser::new().parse(buffer_pinned).await,
+        Ok(Owner { id: 1, name: None })
+    );
+
+    let mut buffer = buffer_with_chunks(&[r#"{"name": "a"}"#]).await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    assert_eq!(
+        OwnerParser::new().parse(buffer_pinned).await,
+        Err(ParseError {
+            message: "Missing field",
+            offset: 13
+        })
+    );
+
+    let mut buffer = buffer_with_chunks(&[
+        r#"{"feedId": 7, "items": [{"id": 1, "tags": []}, {"id": "2", "tags": []}]}"#,
+    ])
+    .await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    assert_eq!(
+        FeedParser::new()
+            .on_items(|_| {})
+            .parse(buffer_pinned)
+            .await,
+        Err(ParseError {
+            message: "Expected an integer",
+            offset: 47
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_parse_struct_nested_streamed_fields() {
+    let items = Arc::new(Mutex::new(Vec::new()));
+    let items_clone = items.clone();
+
+    let mut buffer = buffer_with_chunks(&[
+        r#"{"items": [{"id": 1, "tags": []}], "page": {"number": 2, "#,
+        r#""items": [{"id": 3, "tags": ["b"]}]}, "ids": [4, 5], "owner": null}"#,
+    ])
+    .await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    let listing = ListingParser::new()
+        .on_items(move |x| items_clone.lock().unwrap().push(x))
+        .parse(buffer_pinned)
+        .await;
+
+    // Only the members of the root `items` are passed to the callback
+    assert_eq!(
+        listing,
+        Ok(Listing {
+            items: vec![],
+            page: Page {
+                number: 2,
+                items: vec![Item {
+                    id: 3,
+                    tags: vec!["b".to_string()]
+                }]
+            },
+            ids: Ids { ids: vec![4, 5] },
+            owner: None,
+        })
+    );
+    assert_eq!(
+        *items.lock().unwrap(),
+        vec![Item {
+            id: 1,
+            tags: vec![]
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_parse_struct_nested_structs() {
+    let mut buffer = buffer_with_chunks(&[r#"{"page": 1, "owner": {"id": 2, "x": [3]}}"#]).await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    assert_eq!(
+        ListingParser::new().parse(buffer_pinned).await,
+        Err(ParseError {
+            message: "Expected an object",
+            offset: 41
+        })
+    );
+
+    // The fields of the nested struct are collected without the keys that it does not have
+    let mut buffer =
+        buffer_with_chunks(&[r#"{"page": {"number": 1, "other": {"a": [2]}}, "owner": null}"#])
+            .await;
+    let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+    assert_eq!(
+        ListingParser::new().parse(buffer_pinned).await,
+        Ok(Listing {
+            items: vec![],
+            page: Page {
+                number: 1,
+                items: vec![]
+            },
+            ids: Ids { ids: vec![] },
+            owner: None,
+        })
+    );
+}
@@ -1 +1,4 @@
 pub mod parser;
+
+#[cfg(feature = "derive")]
+pub use inc_json_derive::IncJson;
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+use tokio::{io::AsyncWrite, sync::Mutex};
+
+/// Which characters are used for the last two of the 64 digits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Base64Alphabet {
+    /// `+` and `/`, as in section 4 of RFC 4648
+    Standard,
+    /// `-` and `_`, as in section 5 of RFC 4648
+    UrlSafe,
+}
+
+/// Where decoded bytes are written to, the caller keeps a clone of it to get at the writer
+/// once parsing has finished.
+pub type Base64Writer = Arc<Mutex<dyn AsyncWrite + Send + Unpin>>;
+
+#[derive(Clone)]
+pub(crate) struct Base64Consumer {
+    pub alphabet: Base64Alphabet,
+    pub writer: Base64Writer,
+}
+
+/// Decodes base64 a fragment at a time, padding is optional.
+pub(crate) struct Base64Decoder {
+    alphabet: Base64Alphabet,
+    /// The digits of the group of four that is being read
+    group: [u8; 4],
+    digits: usize,
+    padding: usize,
+    /// The number of chars that have been decoded
+    position: usize,
+}
+
+impl Base64Decoder {
+    pub fn new(alphabet: Base64Alphabet) -> Self {
+        return Base64Decoder {
+            alphabet,
+            group: [0; 4],
+            digits: 0,
+            padding: 0,
+            position: 0,
+        };
+    }
+
+    /// The index of the next char in the base64 text, after an error this is the index of the
+    /// char that is not valid.
+    pub fn position(&self) -> usize {
+        return self.position;
+    }
+
+    fn digit(&self, c: char) -> Option<u8> {
+        return match (c, self.alphabet) {
+            ('A'..='Z', _) => Some(c as u8 - b'A'),
+            ('a'..='z', _) => Some(c as u8 - b'a' + 26),
+            ('0'..='9', _) => Some(c as u8 - b'0' + 52),
+            ('+', Base64Alphabet::Standard) | ('-', Base64Alphabet::UrlSafe) => Some(62),
+            ('/', Base64Alphabet::Standard) | ('_', Base64Alphabet::UrlSafe) => Some(63),
+            _ => None,
+        };
+    }
+
+    /// Appends the bytes of the digits in the current group to `out`.
+    fn end_group(&mut self, out: &mut Vec<u8>) {
+        let bits = self
+            .group
+            .iter()
+            .fold(0u32, |bits, digit| (bits << 6) | *digit as u32);
+        let bytes = bits.to_be_bytes();
+        out.extend_from_slice(&bytes[1..self.digits]);
+
+        self.group = [0; 4];
+        self.digits = 0;
+    }
+
+    /// Decodes the chars appending the bytes to `out`, the last group of digits is kept until
+    /// it is complete or `finish()` is called.
+    pub fn decode(&mut self, chars: &str, out: &mut Vec<u8>) -> Result<(), &'static str> {
+        for c in chars.chars() {
+            if c == '=' {
+                if self.digits + self.padding < 2 || self.digits + self.padding >= 4 {
+                    return Err("Invalid base64 padding");
+                }
+                self.padding += 1;
+            } else {
+                if self.padding > 0 {
+                    return Err("Base64 digit after padding");
+                }
+
+                match self.digit(c) {
+                    Some(digit) => self.group[self.digits] = digit,
+                    None => return Err("Invalid base64 char"),
+                }
+                self.digits += 1;
+                if self.digits == 4 {
+                    self.end_group(out);
+                }
+            }
+
+            self.position += 1;
+        }
+
+        return Ok(());
+    }
+
+    /// Appends the bytes of the last group of digits to `out`.
+    pub fn finish(&mut self, out: &mut Vec<u8>) -> Result<(), &'static str> {
+        if self.padding > 0 && self.digits + self.padding != 4 {
+            return Err("Invalid base64 padding");
+        }
+
+        return match self.digits {
+            0 => Ok(()),
+            1 => Err("Truncated base64"),
+            _ => {
+                self.end_group(out);
+                Ok(())
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod test_base64 {
+    use super::*;
+
+    fn decode(alphabet: Base64Alphabet, chunks: &[&str]) -> Result<Vec<u8>, (&'static str, usize)> {
+        let mut decoder = Base64Decoder::new(alphabet);
+        let mut out = Vec::new();
+        for chunk in chunks {
+            decoder
+                .decode(chunk, &mut out)
+                .map_err(|x| (x, decoder.position()))?;
+        }
+        decoder
+            .finish(&mut out)
+            .map_err(|x| (x, decoder.position()))?;
+        return Ok(out);
+    }
+
+    #[test]
+    fn test_decode_over_many_chunks() {
+        assert_eq!(
+            decode(Base64Alphabet::Standard, &["SGVsb", "G8gd2", "9ybGQ", "="]),
+            Ok(b"Hello world".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_decode_without_padding() {
+        assert_eq!(decode(Base64Alphabet::Standard, &["YQ"]), Ok(b"a".to_vec()));
+        assert_eq!(
+            decode(Base64Alphabet::Standard, &["YWI"]),
+            Ok(b"ab".to_vec())
+        );
+        assert_eq!(decode(Base64Alphabet::Standard, &[""]), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_decode_alphabets() {
+        assert_eq!(
+            decode(Base64Alphabet::Standard, &["+/8="]),
+            Ok(vec![0xfb, 0xff])
+        );
+        assert_eq!(
+            decode(Base64Alphabet::UrlSafe, &["-_8="]),
+            Ok(vec![0xfb, 0xff])
+        );
+        assert_eq!(
+            decode(Base64Alphabet::UrlSafe, &["+/8="]),
+            Err(("Invalid base64 char", 0))
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(
+            decode(Base64Alphabet::Standard, &["YWJj", "Z!"]),
+            Err(("Invalid base64 char", 5))
+        );
+        assert_eq!(
+            decode(Base64Alphabet::Standard, &["YQ==YQ=="]),
+            Err(("Base64 digit after padding", 4))
+        );
+        assert_eq!(
+            decode(Base64Alphabet::Standard, &["Y==="]),
+            Err(("Invalid base64 padding", 1))
+        );
+        assert_eq!(
+            decode(Base64Alphabet::Standard, &["YQ="]),
+            Err(("Invalid base64 padding", 3))
+        );
+        assert_eq!(
+            decode(Base64Alphabet::Standard, &["YWJjZ"]),
+            Err(("Truncated base64", 5))
+        );
+    }
+}
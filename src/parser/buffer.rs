@@ -1,116 +1,790 @@
-use std::pin::Pin;
-use tokio::sync::{Mutex, Semaphore};
+use super::{
+    diagnostic::Snippet,
+    json_path::{ConsumerAction, PathContext, PathSegment, RawConsumer, RawFragment},
+    lexer::tokens::whitespace_token::is_whitespace,
+};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::{Mutex, Notify};
 
 /// The buffer reads chunks of data at a time and adds it to an internal queue.
 pub type BufferChunk = Vec<char>;
 
-/// Stores a buffer of incoming characters as a vector of strings (in `Vec<char>` form).
+/// How many bytes of the input that has been read are kept for showing where errors are
+const HISTORY_LENGTH: usize = 4 * 1024;
+
+/// How much data can be queued in a bounded buffer before adding more waits for it to be read.
+/// Sizes are in bytes of UTF-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferLimits {
+    /// Adding data waits once this much is queued
+    pub high_water_mark: usize,
+    /// Adding data carries on once the queue has been read down to this much
+    pub low_water_mark: usize,
+}
+
+impl BufferLimits {
+    /// Limits that carry on adding once half of the high-water mark is left in the queue.
+    pub fn new(high_water_mark: usize) -> Self {
+        return BufferLimits {
+            high_water_mark,
+            low_water_mark: high_water_mark / 2,
+        };
+    }
+
+    pub fn low_water_mark(mut self, low_water_mark: usize) -> Self {
+        self.low_water_mark = low_water_mark.min(self.high_water_mark);
+        return self;
+    }
+}
+
+/// How much data is queued in a buffer and how often adding data has had to wait.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferMetrics {
+    /// Bytes that have been added but not read yet
+    pub queued_bytes: usize,
+    /// The most bytes that have been queued at once
+    pub peak_queued_bytes: usize,
+    /// Chunks that have been added but not fully read
+    pub queued_chunks: usize,
+    /// How many times adding data waited for the queue to be read
+    pub producer_waits: usize,
+}
+
+/// Stores a buffer of incoming characters as a vector of strings, so that parts of a chunk can
+/// be borrowed as a `&str` without copying them.
 pub struct Buffer {
-    sem: Semaphore,
+    shared: Arc<SharedBuffer>,
+}
+
+/// Adds data to a buffer from another task, made with `Buffer::channel()`. Dropping the writer
+/// ends the input as if `eof()` had been called.
+pub struct BufferWriter {
+    shared: Arc<SharedBuffer>,
+}
+
+/// The state that the reading and writing halves of a buffer share.
+struct SharedBuffer {
     data: Mutex<BufferInternalData>,
+    /// Wakes the reader when data is added or the input ends
+    readable: Notify,
+    /// Wakes producers that are waiting for the queue to be read down to the low-water mark
+    space: Notify,
+    /// Whether or not there is more data to be expected after the end of the buffer. This is
+    /// outside of the lock so that it can be set when the writer is dropped.
+    eof: AtomicBool,
+    /// Whether the reader closed the buffer before the end of the input
+    closed: AtomicBool,
 }
 
 struct BufferInternalData {
-    buffers: Vec<BufferChunk>,
-    current_buffer_idx: usize,
-    /// Whether or not there is more data to be expected after the end of the buffer.
-    eof: bool,
+    /// The chunks that have not been read fully, the first chunk is kept until the next char
+    /// is read after it so that borrowing chars stops at the end of it
+    buffers: VecDeque<String>,
+    /// The byte index of the next char in the first chunk
+    cursor: usize,
+    /// Chars that were put back and are read before the chunks, the last one is read first
+    pushed_back: String,
+    /// The number of bytes that have been read from the start of the input
+    offset: usize,
+    capture: Option<Capture>,
+    /// The last char that was read, until it is put back
+    last_char: Option<char>,
+    history: History,
+    /// `None` when any amount of data can be queued
+    limits: Option<BufferLimits>,
+    metrics: BufferMetrics,
+    producer_waiting: bool,
+}
+
+impl BufferInternalData {
+    /// Records that bytes have been read, waking producers if they are waiting for the queue to
+    /// be read down to the low-water mark.
+    fn read(&mut self, bytes: usize, space: &Notify) {
+        self.metrics.queued_bytes = self.metrics.queued_bytes.saturating_sub(bytes);
+        let has_space = self
+            .limits
+            .is_some_and(|x| self.metrics.queued_bytes <= x.low_water_mark);
+        if self.producer_waiting && has_space {
+            self.producer_waiting = false;
+            space.notify_waiters();
+        }
+    }
+
+    /// Moves the cursor past bytes of the first chunk.
+    fn advance(&mut self, bytes: usize, space: &Notify) {
+        self.cursor += bytes;
+        self.offset += bytes;
+        self.read(bytes, space);
+    }
+
+    /// Records a char that has been read, the cursor has already been moved past it.
+    fn consume(&mut self, c: char, space: &Notify) {
+        let offset = self.offset;
+        self.offset += c.len_utf8();
+        self.last_char = Some(c);
+        self.read(c.len_utf8(), space);
+        let mut encoded = [0; 4];
+        self.history.push_str(c.encode_utf8(&mut encoded));
+        if let Some(capture) = self.capture.as_mut() {
+            capture.push(c, offset);
+        }
+    }
+
+    /// The next char that would be read, if it has been added
+    fn next_unread_char(&self) -> Option<char> {
+        return self
+            .pushed_back
+            .chars()
+            .next_back()
+            .or_else(|| self.unread_chunks().flat_map(str::chars).next());
+    }
+
+    /// The parts of the chunks that have not been read yet
+    fn unread_chunks(&self) -> impl Iterator<Item = &str> {
+        return self.buffers.iter().enumerate().map(|(i, x)| {
+            if i == 0 {
+                &x[self.cursor..]
+            } else {
+                &x[..]
+            }
+        });
+    }
+}
+
+/// The last part of the input that has been read, so that the line an error is on can be shown
+/// after the chunk that it was in has been dropped.
+struct History {
+    text: String,
+    /// The byte offset, line and column of the first char of the text
+    start: usize,
+    line: usize,
+    column: usize,
+}
+
+impl History {
+    fn new(start: usize) -> Self {
+        return History {
+            text: String::new(),
+            start,
+            line: 1,
+            column: 1,
+        };
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.text.push_str(s);
+        if self.text.len() < HISTORY_LENGTH * 2 {
+            return;
+        }
+
+        // Dropping half at a time means that each char is only moved once or twice
+        let mut end = self.text.len() - HISTORY_LENGTH;
+        while !self.text.is_char_boundary(end) {
+            end += 1;
+        }
+        for c in self.text[..end].chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        self.text.drain(..end);
+        self.start += end;
+    }
+
+    /// The byte offset of the char before the offset, if it is in the history
+    fn previous_char(&self, offset: usize) -> Option<usize> {
+        let end = offset.checked_sub(self.start)?;
+        let c = self.text.get(..end)?.chars().next_back()?;
+        return Some(offset - c.len_utf8());
+    }
+
+    /// The line that the offset is on, `rest` is the input after the history that has not been
+    /// read yet.
+    fn snippet(&self, offset: usize, rest: &str) -> Option<Snippet> {
+        let i = offset.checked_sub(self.start)?;
+        let before = self.text.get(..i)?;
+        let line_start = before.rfind('\n').map(|x| x + 1);
+        let line = self.line + before.matches('\n').count();
+        let column = match line_start {
+            Some(x) => before[x..].chars().count() + 1,
+            None => self.column + before.chars().count(),
+        };
+
+        let mut after = self.text[i..].to_string();
+        if !after.contains('\n') {
+            after.push_str(rest);
+        }
+        return Some(Snippet::new(
+            line,
+            column,
+            &before[line_start.unwrap_or(0)..],
+            &after,
+            line_start.is_none() && self.column > 1,
+        ));
+    }
+}
+
+/// Records the characters read from the buffer so that they can be passed on verbatim.
+struct Capture {
+    consumer: RawConsumer,
+    /// The path to the captured value
+    path: Vec<PathSegment>,
+    /// Characters that have been read but not yet passed to the consumer
+    pending: String,
+    /// Leading whitespace is not part of the captured value
+    started: bool,
+    /// The byte offset of the first char of the value
+    start: usize,
+    /// What the consumer wants to do next, once it is not `Continue` the consumer is not called
+    action: ConsumerAction,
+    /// How many chars were put back after they had been passed to the consumer, they are not
+    /// passed on again when they are read again
+    replayed: usize,
+}
+
+impl Capture {
+    /// Records a char that was read at the byte offset
+    fn push(&mut self, c: char, offset: usize) {
+        if self.replayed > 0 {
+            self.replayed -= 1;
+            return;
+        }
+        if !self.started {
+            if is_whitespace(c) {
+                return;
+            }
+            self.started = true;
+            self.start = offset;
+        }
+
+        self.pending.push(c);
+    }
+
+    /// Takes back a char that was put back into the buffer, if it has not been passed on yet.
+    fn unread(&mut self, c: char) {
+        if self.pending.ends_with(c) {
+            self.pending.pop();
+        } else {
+            self.replayed += 1;
+        }
+    }
+
+    /// Passes everything up to the last non-whitespace character to the consumer, trailing
+    /// whitespace is kept back as it may not be part of the value (i.e: after a number).
+    fn flush(&mut self) {
+        let end = self.pending.trim_end_matches(is_whitespace).len();
+        if end == 0 {
+            return;
+        }
+
+        if self.action == ConsumerAction::Continue {
+            let context = PathContext::new(&self.path, self.start);
+            self.action = (self.consumer)(RawFragment::Chunk(&self.pending[..end]), &context);
+        }
+        self.pending.drain(..end);
+    }
+
+    /// Passes the rest of the value to the consumer followed by the end of it.
+    fn finish(&mut self) {
+        self.flush();
+        if self.action == ConsumerAction::Continue {
+            let context = PathContext::new(&self.path, self.start);
+            self.action = (self.consumer)(RawFragment::End, &context);
+        }
+    }
+}
+
+impl SharedBuffer {
+    async fn metrics(&self) -> BufferMetrics {
+        let data = self.data.lock().await;
+        let mut metrics = data.metrics;
+        metrics.queued_chunks = data.unread_chunks().filter(|x| !x.is_empty()).count();
+        return metrics;
+    }
+
+    async fn add_string(&self, chunk: String) -> Result<(), &'static str> {
+        let mut data = self.data.lock().await;
+        if let Some(limits) = data.limits {
+            if data.metrics.queued_bytes >= limits.high_water_mark && !self.is_ended() {
+                data.metrics.producer_waits += 1;
+                loop {
+                    // Created before checking so that a wake up after checking is not missed
+                    let space = self.space.notified();
+                    if data.metrics.queued_bytes <= limits.low_water_mark || self.is_ended() {
+                        break;
+                    }
+                    data.producer_waiting = true;
+                    drop(data);
+                    space.await;
+                    data = self.data.lock().await;
+                }
+            }
+        }
+        if self.is_ended() {
+            return Result::Err("Cannot add data once the EOF has occurred");
+        }
+
+        data.metrics.queued_bytes += chunk.len();
+        data.metrics.peak_queued_bytes = data
+            .metrics
+            .peak_queued_bytes
+            .max(data.metrics.queued_bytes);
+        if !chunk.is_empty() {
+            data.buffers.push_back(chunk);
+        }
+        self.readable.notify_one();
+        Result::Ok(())
+    }
+
+    /// Marks the end of the input, waking the reader and any producers that are waiting.
+    fn end(&self) {
+        if !self.eof.swap(true, Ordering::SeqCst) {
+            self.readable.notify_one();
+            self.space.notify_waiters();
+        }
+    }
+
+    fn is_ended(&self) -> bool {
+        return self.eof.load(Ordering::SeqCst);
+    }
+}
+
+impl BufferWriter {
+    /// Adds a chunk of data to the buffer
+    pub async fn add_data(&mut self, chunk: BufferChunk) -> Result<(), &'static str> {
+        return self.shared.add_string(chunk.into_iter().collect()).await;
+    }
+
+    /// Adds a chunk of data to the buffer without converting it, this waits while a bounded
+    /// buffer is full.
+    pub async fn add_string(&mut self, chunk: String) -> Result<(), &'static str> {
+        return self.shared.add_string(chunk).await;
+    }
+
+    /// Called when at the end of the input.
+    pub async fn eof(&mut self) {
+        self.shared.end();
+    }
+
+    /// How much data is queued and how often adding data has waited.
+    pub async fn metrics(&self) -> BufferMetrics {
+        return self.shared.metrics().await;
+    }
+}
+
+impl Drop for BufferWriter {
+    fn drop(&mut self) {
+        self.shared.end();
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // Producers that are waiting for space fail rather than waiting for a reader that is gone
+        self.shared.end();
+    }
 }
 
 impl Buffer {
     pub fn new() -> Self {
         println!("uwu");
-        Buffer {
+        return Buffer::with_limits(None);
+    }
+
+    fn with_limits(limits: Option<BufferLimits>) -> Self {
+        let shared = SharedBuffer {
             data: Mutex::new(BufferInternalData {
-                buffers: Vec::new(),
-                current_buffer_idx: 0,
-                eof: false,
+                buffers: VecDeque::new(),
+                cursor: 0,
+                pushed_back: String::new(),
+                offset: 0,
+                capture: None,
+                last_char: None,
+                history: History::new(0),
+                limits,
+                metrics: BufferMetrics::default(),
+                producer_waiting: false,
             }),
-            sem: Semaphore::new(0),
-        }
+            readable: Notify::new(),
+            space: Notify::new(),
+            eof: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        };
+        return Buffer {
+            shared: Arc::new(shared),
+        };
+    }
+
+    /// A buffer where adding data waits while the high-water mark of data is queued, until the
+    /// parser has read it down to the low-water mark. This stops a fast producer from filling
+    /// memory when the parser cannot keep up.
+    pub fn bounded(limits: BufferLimits) -> Self {
+        return Buffer::with_limits(Some(limits));
+    }
+
+    /// Splits a buffer into a writer that can be moved to another task and the buffer that the
+    /// parser reads from, so that data can be added while it is being parsed.
+    pub fn channel() -> (BufferWriter, Buffer) {
+        let buffer = Buffer::with_limits(None);
+        let writer = BufferWriter {
+            shared: buffer.shared.clone(),
+        };
+        return (writer, buffer);
+    }
+
+    /// A bounded buffer split into a writer and the buffer that the parser reads from, the
+    /// writer waits while the queue is full.
+    pub fn bounded_channel(limits: BufferLimits) -> (BufferWriter, Buffer) {
+        let buffer = Buffer::with_limits(Some(limits));
+        let writer = BufferWriter {
+            shared: buffer.shared.clone(),
+        };
+        return (writer, buffer);
+    }
+
+    /// How much data is queued and how often adding data has waited.
+    pub async fn metrics(&self) -> BufferMetrics {
+        return self.shared.metrics().await;
     }
 
     /// Adds a chunk of data to the buffer
     pub async fn add_data(&mut self, chunk: BufferChunk) -> Result<(), &'static str> {
-        let mut data = self.data.lock().await;
-        if data.eof {
-            return Result::Err("Cannot add data once the EOF has occurred");
-        }
+        return self.shared.add_string(chunk.into_iter().collect()).await;
+    }
 
-        data.buffers.push(chunk);
-        self.sem.add_permits(1);
-        Result::Ok(())
+    /// Adds a chunk of data to the buffer without converting it. If the buffer is bounded and
+    /// full this waits until enough of it has been read, the chunk is not added if this is
+    /// cancelled while waiting.
+    pub async fn add_string(&mut self, chunk: String) -> Result<(), &'static str> {
+        return self.shared.add_string(chunk).await;
     }
 
     /// Called when at the end of the buffer.
     pub async fn eof(&mut self) {
-        self.data.lock().await.eof = true;
-        self.sem.add_permits(1);
+        self.shared.end();
     }
 
+    /// Whether the EOF has occurred and every character before it has been read.
     pub async fn is_eof(&mut self) -> bool {
-        let data = self.data.lock().await;
-        if !data.eof {
+        if !self.shared.is_ended() {
             return false;
         }
+        let data = self.shared.data.lock().await;
+        return data.pushed_back.is_empty() && data.unread_chunks().all(str::is_empty);
+    }
 
-        return data.buffers.is_empty();
+    /// The byte offset of the next char from the start of the input.
+    pub async fn offset(&self) -> usize {
+        return self.shared.data.lock().await.offset;
     }
 
-    pub async fn replace_char(&mut self, c: char) {
-        let mut data = self.data.lock().await;
-        let mut new_buffer = Vec::new();
-        new_buffer.push(c);
+    /// Sets the byte offset of the next char, for when the data does not start at the start of
+    /// the input.
+    pub async fn set_offset(&mut self, offset: usize) {
+        let mut data = self.shared.data.lock().await;
+        data.offset = offset;
+        data.history = History::new(offset);
+    }
+
+    /// The line of the input that the byte offset is on, if it has been read recently enough
+    /// to still be in the buffer's history. Lines are counted from the start of the data.
+    pub async fn snippet(&self, offset: usize) -> Option<Snippet> {
+        let data = self.shared.data.lock().await;
+        let pushed_back: String = data.pushed_back.chars().rev().collect();
+        let mut rest = String::new();
+        for x in std::iter::once(&pushed_back[..]).chain(data.unread_chunks()) {
+            match x.find('\n') {
+                Some(end) => {
+                    rest.push_str(&x[..end]);
+                    break;
+                }
+                None => rest.push_str(x),
+            }
+        }
+        return data.history.snippet(offset, &rest);
+    }
+
+    /// The byte offset of the char that was read before the offset, if it is still in the
+    /// buffer's history.
+    pub async fn previous_char_offset(&self, offset: usize) -> Option<usize> {
+        return self.shared.data.lock().await.history.previous_char(offset);
+    }
+
+    /// Starts passing each character read from the buffer to the consumer, whitespace before
+    /// the first character is skipped. Characters are passed on a chunk at a time along with
+    /// the path to the value.
+    pub async fn start_capture(&mut self, consumer: RawConsumer, path: Vec<PathSegment>) {
+        self.shared.data.lock().await.capture = Some(Capture {
+            consumer,
+            path,
+            pending: String::new(),
+            started: false,
+            start: 0,
+            action: ConsumerAction::Continue,
+            replayed: 0,
+        });
+    }
+
+    /// Passes the remaining captured characters and the end of the value to the consumer, then
+    /// stops capturing. The action that the consumer returned is returned.
+    pub async fn end_capture(&mut self) -> ConsumerAction {
+        return match self.shared.data.lock().await.capture.take() {
+            Some(mut capture) => {
+                capture.finish();
+                capture.action
+            }
+            None => ConsumerAction::Continue,
+        };
+    }
+
+    /// Stops capturing without passing the end of the value to the consumer, for when the value
+    /// turned out not to be valid.
+    pub async fn cancel_capture(&mut self) {
+        self.shared.data.lock().await.capture = None;
+    }
+
+    /// Stops the buffer early, any data that has not been read is dropped and no more data can
+    /// be added.
+    pub async fn close(&mut self) {
+        let mut data = self.shared.data.lock().await;
+        self.shared.eof.store(true, Ordering::SeqCst);
+        self.shared.closed.store(true, Ordering::SeqCst);
+        data.buffers.clear();
+        data.cursor = 0;
+        data.pushed_back.clear();
+        data.capture = None;
+        let queued = data.metrics.queued_bytes;
+        data.read(queued, &self.shared.space);
+        // Producers that are waiting fail now that no more data can be added
+        self.shared.space.notify_waiters();
+        self.shared.readable.notify_one();
+    }
+
+    /// Whether `close()` stopped the buffer, writing to it fails after that.
+    pub fn is_closed(&self) -> bool {
+        return self.shared.closed.load(Ordering::SeqCst);
+    }
+
+    /// The next char without reading it, this waits until it has been added. `None` is
+    /// returned at the end of the input.
+    pub async fn peek(&mut self) -> Option<char> {
+        loop {
+            let data = self.shared.data.lock().await;
+            if let Some(c) = data.next_unread_char() {
+                return Some(c);
+            }
+
+            let readable = self.shared.readable.notified();
+            if self.shared.is_ended() {
+                return None;
+            }
+            drop(data);
+            readable.await;
+        }
+    }
 
-        if !data.buffers.is_empty() {
-            let mut i = data.current_buffer_idx;
-            while i < data.buffers[0].len() {
-                new_buffer.push(data.buffers[0][i]);
+    /// The next `n` chars without reading them, this waits until they have been added. Fewer
+    /// chars are returned if the input ends first.
+    pub async fn peek_n(&mut self, n: usize) -> String {
+        loop {
+            let data = self.shared.data.lock().await;
+            let chunks = data.unread_chunks().flat_map(str::chars);
+            let peeked: Vec<char> = data
+                .pushed_back
+                .chars()
+                .rev()
+                .chain(chunks)
+                .take(n)
+                .collect();
+            if peeked.len() >= n {
+                return peeked.into_iter().collect();
+            }
 
-                i += 1
+            let readable = self.shared.readable.notified();
+            if self.shared.is_ended() {
+                return peeked.into_iter().collect();
             }
+            drop(data);
+            readable.await;
+        }
+    }
+
+    /// Puts a char that was read back so that it is read again. Many chars can be put back as
+    /// long as it is in the reverse of the order that they were read in. This does not change
+    /// any chunks.
+    pub async fn unread(&mut self, c: char) {
+        let mut data = self.shared.data.lock().await;
+        if let Some(capture) = data.capture.as_mut() {
+            capture.unread(c);
+        }
+        data.offset = data.offset.saturating_sub(c.len_utf8());
+        data.metrics.queued_bytes += c.len_utf8();
+        data.last_char = None;
+        // The history may have been trimmed past the char
+        if data.history.text.ends_with(c) {
+            data.history.text.pop();
+        }
 
-            data.buffers.remove(0);
+        // The char is usually the one before the cursor, so the cursor is moved back
+        let cursor = data.cursor;
+        let before_cursor = data.pushed_back.is_empty()
+            && data
+                .buffers
+                .front()
+                .is_some_and(|x| x[..cursor].ends_with(c));
+        if before_cursor {
+            data.cursor -= c.len_utf8();
+        } else {
+            data.pushed_back.push(c);
         }
+    }
 
-        data.buffers.insert(0, new_buffer);
-        data.current_buffer_idx = 0;
-        self.sem.add_permits(1);
+    /// Puts the char back into the buffer, see `unread`.
+    pub async fn replace_char(&mut self, c: char) {
+        self.unread(c).await;
+    }
+
+    /// Puts the last char that was read back into the buffer so that it is read again, for when
+    /// a scanner failed on a char that is not part of the token it was scanning. Nothing is put
+    /// back if the char has already been put back.
+    pub async fn unread_last_char(&mut self) {
+        let last_char = self.shared.data.lock().await.last_char;
+        if let Some(c) = last_char {
+            self.unread(c).await;
+        }
+    }
+
+    /// Passes the rest of a string to `f` as a slice of the current chunk without copying it,
+    /// the opening quote has already been read. This can only be done when there are no escape
+    /// sequences and the closing quote is in the current chunk, otherwise `None` is returned
+    /// and nothing is read. The closing quote is read when the string is borrowed.
+    pub async fn borrow_plain_string<R>(&mut self, f: impl FnOnce(&str) -> R) -> Option<R> {
+        let mut data = self.shared.data.lock().await;
+        let BufferInternalData {
+            buffers,
+            cursor,
+            pushed_back,
+            offset,
+            capture,
+            last_char,
+            history,
+            ..
+        } = &mut *data;
+
+        if !pushed_back.is_empty() {
+            return None;
+        }
+        let rest = &buffers.front()?[*cursor..];
+        let end = rest.find(['"', '\\'])?;
+        if !rest[end..].starts_with('"') {
+            return None;
+        }
+
+        let string = &rest[..end];
+        if let Some(capture) = capture.as_mut() {
+            string
+                .char_indices()
+                .for_each(|(i, c)| capture.push(c, *offset + i));
+            capture.push('"', *offset + end);
+        }
+
+        let res = f(string);
+        *last_char = Some('"');
+        history.push_str(string);
+        history.push_str("\"");
+        data.advance(end + 1, &self.shared.space);
+        return Some(res);
+    }
+
+    /// Passes the chars up to the next quote or escape in the current chunk to `f` without
+    /// copying them, these chars are then read. `None` is returned if there are no such chars,
+    /// i.e: the next char is a quote or the current chunk has all been read.
+    pub async fn borrow_plain_chars<R>(&mut self, f: impl FnOnce(&str) -> R) -> Option<R> {
+        let mut data = self.shared.data.lock().await;
+        let BufferInternalData {
+            buffers,
+            cursor,
+            pushed_back,
+            offset,
+            capture,
+            last_char,
+            history,
+            ..
+        } = &mut *data;
+
+        if !pushed_back.is_empty() {
+            return None;
+        }
+        let rest = &buffers.front()?[*cursor..];
+        let end = rest.find(['"', '\\']).unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+
+        let chars = &rest[..end];
+        if let Some(capture) = capture.as_mut() {
+            chars
+                .char_indices()
+                .for_each(|(i, c)| capture.push(c, *offset + i));
+        }
+
+        *last_char = chars.chars().last();
+        history.push_str(chars);
+        let res = f(chars);
+        data.advance(end, &self.shared.space);
+        return Some(res);
     }
 
     pub async fn next_char(self: &mut Pin<Box<&mut Self>>) -> Result<char, &'static str> {
         loop {
-            let mut data = self.data.lock().await;
-            let buffer = data.buffers.first();
-            let at_end_of_current_buffer = match buffer {
-                Some(b) => data.current_buffer_idx >= b.len(),
-                None => true,
-            };
+            let mut data = self.shared.data.lock().await;
+            if let Some(c) = data.pushed_back.pop() {
+                data.consume(c, &self.shared.space);
+                return Ok(c);
+            }
 
-            if at_end_of_current_buffer {
-                if data.eof && data.buffers.is_empty() {
-                    return Err("EOF reached");
+            let next = data
+                .buffers
+                .front()
+                .and_then(|x| x[data.cursor..].chars().next());
+
+            let Some(c) = next else {
+                // Captured chars are passed on a chunk at a time
+                if let Some(capture) = data.capture.as_mut() {
+                    capture.flush();
                 }
 
-                // Drop to prevent dead-lock
-                drop(data);
-                if self.sem.acquire().await.is_err() {
-                    return Err("Cannot unlock semaphore - EOF probably");
+                if data.buffers.len() > 1 {
+                    data.buffers.pop_front();
+                    data.cursor = 0;
+                    continue;
                 }
 
-                let mut data = self.data.lock().await;
-                // The first buffer is the one that has been read fully
-                if data.eof && data.buffers.len() <= 1 {
+                // Created before checking so that data added after checking wakes the reader
+                let readable = self.shared.readable.notified();
+                if self.shared.is_ended() {
                     return Err("EOF reached");
                 }
 
-                data.current_buffer_idx = 0;
-                data.buffers.remove(0);
-            } else {
-                let c = buffer.unwrap()[data.current_buffer_idx];
-                data.current_buffer_idx += 1;
-                return Ok(c);
-            }
+                // Drop to prevent dead-lock
+                drop(data);
+                readable.await;
+                continue;
+            };
+
+            data.cursor += c.len_utf8();
+            data.consume(c, &self.shared.space);
+            return Ok(c);
         }
     }
 }
@@ -118,7 +792,8 @@ impl Buffer {
 #[cfg(test)]
 mod test_buffer {
     use super::*;
-    use std::borrow::BorrowMut;
+    use std::{borrow::BorrowMut, time::Duration};
+    use tokio::time::timeout;
 
     #[tokio::test]
     async fn test_cannot_add_data_after_eof() {
@@ -239,28 +914,360 @@ mod test_buffer {
         assert_eq!(c1.unwrap(), 'a');
     }
 
-    // #[tokio::test]
-    // async fn test_next_char_many_buffers_with_wait() {
-    //     let mut buf = Buffer::new();
-    //
-    //     let mut buffer = Box::pin(buf.borrow_mut());
-    //     buffer
-    //         .add_data(vec!['h'])
-    //         .await
-    //         .unwrap();
-    //
-    //     let mut buffer = Box::pin(buf.borrow_mut());
-    //     let c1 = buffer.next_char().await.unwrap();
-    //     assert_eq!(c1, 'h');
-    //
-    //     let c2_future = buffer.next_char();
-    //
-    //     buffer
-    //         .add_data(vec!['e'])
-    //         .await
-    //         .unwrap();
-    //
-    //     let c2 = c2_future.await.unwrap();
-    //     assert_eq!(c2, 'e');
-    // }
+    #[tokio::test]
+    async fn test_next_char_many_buffers_with_wait() {
+        let (mut writer, mut buf) = Buffer::channel();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        writer.add_data(vec!['h']).await.unwrap();
+
+        let c1 = buffer.next_char().await.unwrap();
+        assert_eq!(c1, 'h');
+
+        let producer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            writer.add_data(vec!['e']).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            writer.add_string("y".to_string()).await.unwrap();
+        });
+
+        let c2 = buffer.next_char().await.unwrap();
+        assert_eq!(c2, 'e');
+        assert_eq!(buffer.next_char().await.unwrap(), 'y');
+
+        // Dropping the writer ends the input
+        producer.await.unwrap();
+        assert!(buffer.next_char().await.is_err());
+        assert!(buffer.is_eof().await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_bounded_channel_across_tasks() {
+        let (mut writer, mut buf) = Buffer::bounded_channel(BufferLimits::new(16));
+        let mut buffer = Box::pin(buf.borrow_mut());
+        let producer = tokio::spawn(async move {
+            for i in 0..100 {
+                writer.add_string(format!("{:04},", i)).await.unwrap();
+            }
+            writer.eof().await;
+            return writer.metrics().await;
+        });
+
+        let mut read = String::new();
+        while let Ok(c) = buffer.next_char().await {
+            read.push(c);
+        }
+        let expected: String = (0..100).map(|i| format!("{:04},", i)).collect();
+        assert_eq!(read, expected);
+
+        let metrics = producer.await.unwrap();
+        assert!(metrics.producer_waits > 0);
+        assert!(metrics.peak_queued_bytes <= 20);
+    }
+
+    #[tokio::test]
+    async fn test_writer_fails_once_buffer_is_dropped() {
+        let (mut writer, buffer) = Buffer::bounded_channel(BufferLimits::new(4));
+        writer.add_string("abcd".to_string()).await.unwrap();
+
+        let producer = tokio::spawn(async move {
+            return writer.add_string("efgh".to_string()).await;
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(buffer);
+        assert!(producer.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bounded_buffer_waits_to_add() {
+        let mut buf = Buffer::bounded(BufferLimits::new(8).low_water_mark(2));
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer.add_string("abcdef".to_string()).await.unwrap();
+        buffer.add_string("ghij".to_string()).await.unwrap();
+
+        let wait = Duration::from_millis(10);
+        let res = timeout(wait, buffer.add_string("klm".to_string())).await;
+        assert!(res.is_err(), "Should wait while full");
+        assert_eq!(
+            buffer.metrics().await,
+            BufferMetrics {
+                queued_bytes: 10,
+                peak_queued_bytes: 10,
+                queued_chunks: 2,
+                producer_waits: 1,
+            }
+        );
+
+        for c in "abcdefg".chars() {
+            assert_eq!(buffer.next_char().await.unwrap(), c);
+        }
+        let res = timeout(wait, buffer.add_string("klm".to_string())).await;
+        assert_eq!(res, Ok(Ok(())));
+        assert_eq!(buffer.metrics().await.queued_bytes, 6);
+        assert_eq!(buffer.metrics().await.queued_chunks, 2);
+
+        buffer.close().await;
+        assert_eq!(buffer.metrics().await.queued_bytes, 0);
+        assert!(buffer.add_string("n".to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_close_drops_unread_data() {
+        let mut buf = Buffer::new();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer.add_data(vec!['h', 'e']).await.unwrap();
+        buffer.add_data(vec!['y']).await.unwrap();
+
+        assert_eq!(buffer.next_char().await.unwrap(), 'h');
+        buffer.close().await;
+
+        assert!(buffer.is_eof().await);
+        assert!(buffer.next_char().await.is_err());
+        assert!(buffer.add_data(vec!['a']).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_next_char_multi_byte_chars() {
+        let mut buf = Buffer::new();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer.add_string("🤠é".to_string()).await.unwrap();
+        buffer.add_data(vec!['➽']).await.unwrap();
+        buffer.eof().await;
+
+        assert_eq!(buffer.next_char().await.unwrap(), '🤠');
+        buffer.replace_char('🤠').await;
+        assert_eq!(buffer.next_char().await.unwrap(), '🤠');
+        assert_eq!(buffer.next_char().await.unwrap(), 'é');
+        assert_eq!(buffer.next_char().await.unwrap(), '➽');
+        assert!(buffer.next_char().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_borrow_plain_string() {
+        let mut buf = Buffer::new();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer
+            .add_string("\"héllo\", \"a\\\"b\"".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(buffer.next_char().await.unwrap(), '"');
+        let res = buffer.borrow_plain_string(|x| x.to_string()).await;
+        assert_eq!(res, Some("héllo".to_string()));
+        assert_eq!(buffer.next_char().await.unwrap(), ',');
+        assert_eq!(buffer.next_char().await.unwrap(), ' ');
+        assert_eq!(buffer.next_char().await.unwrap(), '"');
+
+        // The escape sequence means that the string has to be copied
+        assert!(buffer.borrow_plain_string(|_| ()).await.is_none());
+        assert_eq!(buffer.next_char().await.unwrap(), 'a');
+    }
+
+    #[tokio::test]
+    async fn test_borrow_plain_string_over_many_chunks() {
+        let mut buf = Buffer::new();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer.add_string("\"ab".to_string()).await.unwrap();
+        buffer.add_string("c\"".to_string()).await.unwrap();
+
+        assert_eq!(buffer.next_char().await.unwrap(), '"');
+        assert!(buffer.borrow_plain_string(|_| ()).await.is_none());
+        assert_eq!(buffer.next_char().await.unwrap(), 'a');
+    }
+
+    #[tokio::test]
+    async fn test_borrow_plain_chars() {
+        let mut buf = Buffer::new();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer.add_string("ab\\nc".to_string()).await.unwrap();
+        buffer.add_string("d\"".to_string()).await.unwrap();
+
+        let res = buffer.borrow_plain_chars(|x| x.to_string()).await;
+        assert_eq!(res, Some("ab".to_string()));
+        assert!(buffer.borrow_plain_chars(|_| ()).await.is_none());
+        assert_eq!(buffer.next_char().await.unwrap(), '\\');
+        assert_eq!(buffer.next_char().await.unwrap(), 'n');
+
+        let res = buffer.borrow_plain_chars(|x| x.to_string()).await;
+        assert_eq!(res, Some("c".to_string()));
+        assert!(buffer.borrow_plain_chars(|_| ()).await.is_none());
+        assert_eq!(buffer.next_char().await.unwrap(), 'd');
+    }
+
+    #[tokio::test]
+    async fn test_offset() {
+        let mut buf = Buffer::new();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer.add_string("a➽\"bc\"".to_string()).await.unwrap();
+        buffer.add_string("d".to_string()).await.unwrap();
+        assert_eq!(buffer.offset().await, 0);
+
+        assert_eq!(buffer.next_char().await.unwrap(), 'a');
+        assert_eq!(buffer.next_char().await.unwrap(), '➽');
+        assert_eq!(buffer.offset().await, 4);
+
+        buffer.replace_char('➽').await;
+        assert_eq!(buffer.offset().await, 1);
+        assert_eq!(buffer.next_char().await.unwrap(), '➽');
+        assert_eq!(buffer.next_char().await.unwrap(), '"');
+
+        assert!(buffer.borrow_plain_string(|_| ()).await.is_some());
+        assert_eq!(buffer.offset().await, 8);
+        assert_eq!(buffer.next_char().await.unwrap(), 'd');
+        assert_eq!(buffer.offset().await, 9);
+    }
+
+    #[tokio::test]
+    async fn test_snippet_from_history() {
+        let mut buffer = Buffer::new();
+        let line = format!("{}\n", "a".repeat(99));
+        for _ in 0..100 {
+            buffer.add_string(line.clone()).await.unwrap();
+        }
+        buffer.add_string("bcd\nef".to_string()).await.unwrap();
+        buffer.eof().await;
+
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        for _ in 0..10_002 {
+            buffer_pinned.next_char().await.unwrap();
+        }
+
+        let snippet = buffer_pinned.snippet(10_001).await.unwrap();
+        assert_eq!((snippet.line, snippet.column), (101, 2));
+        assert_eq!((snippet.text.as_str(), snippet.caret), ("bcd", 1));
+        assert_eq!(
+            buffer_pinned.previous_char_offset(10_001).await,
+            Some(10_000)
+        );
+        assert_eq!(buffer_pinned.snippet(100).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_peek_and_unread() {
+        let mut buf = Buffer::new();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer.add_string("tr".to_string()).await.unwrap();
+        buffer.add_string("ue,".to_string()).await.unwrap();
+        buffer.eof().await;
+
+        assert_eq!(buffer.peek().await, Some('t'));
+        assert_eq!(buffer.peek_n(4).await, "true");
+        assert_eq!(buffer.offset().await, 0);
+
+        for c in "true".chars() {
+            assert_eq!(buffer.next_char().await.unwrap(), c);
+        }
+        // Put back past the start of the chunk that is being read
+        for c in "rue".chars().rev() {
+            buffer.unread(c).await;
+        }
+        assert_eq!(buffer.offset().await, 1);
+        assert_eq!(buffer.peek_n(10).await, "rue,");
+        assert!(buffer.borrow_plain_chars(|_| ()).await.is_none());
+
+        for c in "rue,".chars() {
+            assert_eq!(buffer.next_char().await.unwrap(), c);
+        }
+        assert_eq!(buffer.peek().await, None);
+        assert_eq!(buffer.peek_n(2).await, "");
+        assert!(buffer.is_eof().await);
+    }
+
+    #[tokio::test]
+    async fn test_unread_after_capture_flush() {
+        static RAW: std::sync::Mutex<String> = std::sync::Mutex::new(String::new());
+
+        let mut buf = Buffer::new();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        buffer.add_string("ab".to_string()).await.unwrap();
+        buffer.add_string("c]".to_string()).await.unwrap();
+        buffer.eof().await;
+
+        buffer
+            .start_capture(
+                |x, _| {
+                    if let RawFragment::Chunk(s) = x {
+                        RAW.lock().unwrap().push_str(s);
+                    }
+                    ConsumerAction::Continue
+                },
+                Vec::new(),
+            )
+            .await;
+        for c in "abc".chars() {
+            assert_eq!(buffer.next_char().await.unwrap(), c);
+        }
+        // "ab" was passed on when the first chunk was finished
+        assert_eq!(*RAW.lock().unwrap(), "ab");
+        buffer.unread('c').await;
+        buffer.unread('b').await;
+        for c in "bc".chars() {
+            assert_eq!(buffer.next_char().await.unwrap(), c);
+        }
+        buffer.end_capture().await;
+
+        assert_eq!(*RAW.lock().unwrap(), "abc");
+        assert_eq!(buffer.next_char().await.unwrap(), ']');
+    }
+
+    #[tokio::test]
+    async fn test_peek_waits_for_data() {
+        let (mut writer, mut buf) = Buffer::channel();
+        let mut buffer = Box::pin(buf.borrow_mut());
+        writer.add_string("a".to_string()).await.unwrap();
+
+        let producer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            writer.add_string("bc".to_string()).await.unwrap();
+        });
+
+        assert_eq!(buffer.peek_n(3).await, "abc");
+        producer.await.unwrap();
+        assert_eq!(buffer.peek_n(4).await, "abc");
+    }
+
+    /// Reads every char from the string split into chunks at the char indices, putting each
+    /// char back and reading it again when `unread` has it.
+    async fn read_chunked(input: &str, cuts: &[usize], unread: &[bool]) -> String {
+        let chars: Vec<char> = input.chars().collect();
+        let mut cuts: Vec<usize> = cuts.iter().map(|x| x % (chars.len() + 1)).collect();
+        cuts.sort();
+        cuts.push(chars.len());
+
+        let (mut writer, mut buf) = Buffer::channel();
+        let mut start = 0;
+        for end in cuts {
+            writer
+                .add_data(chars[start..end.max(start)].to_vec())
+                .await
+                .unwrap();
+            start = end.max(start);
+        }
+        writer.eof().await;
+
+        let mut buffer = Box::pin(buf.borrow_mut());
+        let mut read = String::new();
+        while let Ok(c) = buffer.next_char().await {
+            if unread.get(read.chars().count()) == Some(&true) {
+                buffer.unread(c).await;
+                assert_eq!(buffer.next_char().await, Ok(c));
+            }
+            read.push(c);
+        }
+        assert!(buffer.is_eof().await);
+        assert_eq!(buffer.offset().await, input.len());
+        return read;
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_any_chunking_reads_the_same_chars(
+            input in "\\PC{0,64}",
+            cuts in proptest::collection::vec(0usize..65, 0..8),
+            unread in proptest::collection::vec(proptest::bool::ANY, 0..64),
+        ) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let read = runtime.block_on(read_chunked(&input, &cuts, &unread));
+            proptest::prop_assert_eq!(read, input);
+        }
+    }
 }
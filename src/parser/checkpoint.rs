@@ -0,0 +1,204 @@
+use indexmap::IndexMap;
+
+use super::{
+    json_path::PathSegment, json_value::JsonValue, lexer::tokens::number_token::NumberToken,
+};
+
+/// An object or array that the parser was inside of when the checkpoint was taken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum CheckpointFrame {
+    Array {
+        start: usize,
+        count: usize,
+    },
+    Object {
+        start: usize,
+        count: usize,
+        /// The required keys that had not been scanned yet
+        missing: Vec<String>,
+    },
+    /// The value of a key in an object, the key is in the path
+    KeyValuePair,
+}
+
+/// The state of a parser between two values, from which parsing can be resumed with the text
+/// from the offset of the checkpoint onwards. The consumers are not saved, so the parser that
+/// is resumed has to have the same consumers as the one that took the checkpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    offset: usize,
+    path: Vec<PathSegment>,
+    frames: Vec<CheckpointFrame>,
+}
+
+/// Called with each checkpoint as it is taken, so that it can be saved.
+pub type CheckpointHandler = fn(checkpoint: &Checkpoint);
+
+fn as_usize(value: Option<&JsonValue>) -> Result<usize, &'static str> {
+    return match value {
+        Some(JsonValue::Number(NumberToken::Integer(x))) if *x >= 0 => Ok(*x as usize),
+        _ => Err("Invalid checkpoint"),
+    };
+}
+
+impl Checkpoint {
+    pub(crate) fn new(offset: usize, path: Vec<PathSegment>, frames: Vec<CheckpointFrame>) -> Self {
+        return Checkpoint {
+            offset,
+            path,
+            frames,
+        };
+    }
+
+    /// The byte offset in the text that parsing resumes from, the text passed to the resumed
+    /// parser has to start here. When the input was decoded or decompressed this is an offset
+    /// in the decoded text, not in the input.
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+
+    pub(crate) fn path(&self) -> &[PathSegment] {
+        return &self.path;
+    }
+
+    pub(crate) fn frames(&self) -> &[CheckpointFrame] {
+        return &self.frames;
+    }
+
+    /// Converts the checkpoint to a value that can be saved with `to_string()`.
+    pub fn to_value(&self) -> JsonValue {
+        let integer = |x: usize| JsonValue::Number(NumberToken::Integer(x as i64));
+
+        let path = self
+            .path
+            .iter()
+            .map(|x| match x {
+                PathSegment::Key(x) => JsonValue::String(x.clone()),
+                PathSegment::Index(x) => integer(*x),
+            })
+            .collect();
+        let frames = self
+            .frames
+            .iter()
+            .map(|x| {
+                let mut frame = IndexMap::new();
+                match x {
+                    CheckpointFrame::Array { start, count } => {
+                        frame.insert("type".to_string(), JsonValue::String("array".to_string()));
+                        frame.insert("start".to_string(), integer(*start));
+                        frame.insert("count".to_string(), integer(*count));
+                    }
+                    CheckpointFrame::Object {
+                        start,
+                        count,
+                        missing,
+                    } => {
+                        frame.insert("type".to_string(), JsonValue::String("object".to_string()));
+                        frame.insert("start".to_string(), integer(*start));
+                        frame.insert("count".to_string(), integer(*count));
+                        frame.insert(
+                            "missing".to_string(),
+                            JsonValue::Array(
+                                missing
+                                    .iter()
+                                    .map(|x| JsonValue::String(x.clone()))
+                                    .collect(),
+                            ),
+                        );
+                    }
+                    CheckpointFrame::KeyValuePair => {
+                        frame.insert("type".to_string(), JsonValue::String("key".to_string()));
+                    }
+                }
+                return JsonValue::Object(frame);
+            })
+            .collect();
+
+        let mut checkpoint = IndexMap::new();
+        checkpoint.insert("offset".to_string(), integer(self.offset));
+        checkpoint.insert("path".to_string(), JsonValue::Array(path));
+        checkpoint.insert("frames".to_string(), JsonValue::Array(frames));
+        return JsonValue::Object(checkpoint);
+    }
+
+    /// Reads a checkpoint that was saved with `to_value()`.
+    pub fn from_value(value: &JsonValue) -> Result<Self, &'static str> {
+        let offset = as_usize(value.get("offset"))?;
+
+        let path = match value.get("path") {
+            Some(JsonValue::Array(x)) => x
+                .iter()
+                .map(|x| match x {
+                    JsonValue::String(x) => Ok::<_, &'static str>(PathSegment::Key(x.clone())),
+                    x => Ok(PathSegment::Index(as_usize(Some(x))?)),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err("Invalid checkpoint"),
+        };
+
+        let frames = match value.get("frames") {
+            Some(JsonValue::Array(x)) => x
+                .iter()
+                .map(|x| match x.get("type").and_then(|x| x.as_str()) {
+                    Some("array") => Ok(CheckpointFrame::Array {
+                        start: as_usize(x.get("start"))?,
+                        count: as_usize(x.get("count"))?,
+                    }),
+                    Some("object") => Ok(CheckpointFrame::Object {
+                        start: as_usize(x.get("start"))?,
+                        count: as_usize(x.get("count"))?,
+                        missing: match x.get("missing") {
+                            Some(JsonValue::Array(x)) => x
+                                .iter()
+                                .map(|x| x.as_str().map(String::from).ok_or("Invalid checkpoint"))
+                                .collect::<Result<_, _>>()?,
+                            _ => return Err("Invalid checkpoint"),
+                        },
+                    }),
+                    Some("key") => Ok(CheckpointFrame::KeyValuePair),
+                    _ => Err("Invalid checkpoint"),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err("Invalid checkpoint"),
+        };
+
+        return Ok(Checkpoint {
+            offset,
+            path,
+            frames,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_checkpoint {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_value_round_trip() {
+        let checkpoint = Checkpoint::new(
+            42,
+            vec![PathSegment::Key("rows".to_string()), PathSegment::Index(3)],
+            vec![
+                CheckpointFrame::Object {
+                    start: 0,
+                    count: 1,
+                    missing: vec!["id".to_string()],
+                },
+                CheckpointFrame::KeyValuePair,
+                CheckpointFrame::Array { start: 9, count: 4 },
+            ],
+        );
+
+        let value = checkpoint.to_value();
+        assert_eq!(
+            value.to_string(),
+            r#"{"offset":42,"path":["rows",3],"frames":[{"type":"object","start":0,"count":1,"missing":["id"]},{"type":"key"},{"type":"array","start":9,"count":4}]}"#
+        );
+        assert_eq!(Checkpoint::from_value(&value), Ok(checkpoint));
+        assert_eq!(
+            Checkpoint::from_value(&JsonValue::Null),
+            Err("Invalid checkpoint")
+        );
+    }
+}
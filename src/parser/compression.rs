@@ -0,0 +1,290 @@
+use super::{
+    buffer::BufferWriter,
+    encoding::{DecodingWriter, OffsetMap},
+    parser::{ParseError, Parser},
+};
+use std::io::{self, Cursor};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, BufReader};
+
+/// How many bytes are read from the input at a time
+const READ_SIZE: usize = 64 * 1024;
+
+/// The formats that compressed input can be in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+const MAGIC_BYTES: [(&[u8], Compression); 3] = [
+    (&[0x1F, 0x8B], Compression::Gzip),
+    (&[0x28, 0xB5, 0x2F, 0xFD], Compression::Zstd),
+    (b"BZh", Compression::Bzip2),
+];
+
+impl Compression {
+    /// Works out the compression from the magic bytes at the start of the input, JSON cannot
+    /// start with any of them.
+    pub fn detect(bytes: &[u8]) -> Self {
+        return MAGIC_BYTES
+            .iter()
+            .find(|(magic, _)| bytes.starts_with(magic))
+            .map(|(_, x)| *x)
+            .unwrap_or(Compression::None);
+    }
+}
+
+/// Wraps the input in a decoder for the compression, the feature for the compression has to be
+/// enabled.
+fn decompress<'a, R: AsyncBufRead + Unpin + 'a>(
+    input: R,
+    compression: Compression,
+) -> Result<Box<dyn AsyncRead + Unpin + 'a>, &'static str> {
+    use async_compression::tokio::bufread;
+
+    return match compression {
+        Compression::None => Ok(Box::new(input)),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => {
+            let mut decoder = bufread::GzipDecoder::new(input);
+            // Large dumps are often written in parallel as many gzip members
+            decoder.multiple_members(true);
+            Ok(Box::new(decoder))
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let mut decoder = bufread::ZstdDecoder::new(input);
+            decoder.multiple_members(true);
+            Ok(Box::new(decoder))
+        }
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => {
+            let mut decoder = bufread::BzDecoder::new(input);
+            decoder.multiple_members(true);
+            Ok(Box::new(decoder))
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err("The input is compressed in a format that is not enabled"),
+    };
+}
+
+fn read_error(e: io::Error) -> &'static str {
+    return match e.kind() {
+        io::ErrorKind::InvalidData => "Invalid compressed data",
+        io::ErrorKind::UnexpectedEof => "The compressed input ended early",
+        _ => "Cannot read the input",
+    };
+}
+
+/// Reads all of the input into the buffer, decompressing it if it starts with the magic bytes
+/// of gzip, zstd or bzip2 and then decoding the text. Nothing is read past what the buffer can
+/// queue, so the input is never decompressed all at once. The offset of an error is in the
+/// decompressed input.
+pub async fn read_into_buffer<R: AsyncRead + Unpin>(
+    reader: R,
+    writer: BufferWriter,
+) -> Result<(), ParseError> {
+    return read_decoded(reader, DecodingWriter::new(writer)).await;
+}
+
+async fn read_decoded<R: AsyncRead + Unpin>(
+    mut reader: R,
+    mut writer: DecodingWriter,
+) -> Result<(), ParseError> {
+    let fail = |message| ParseError { message, offset: 0 };
+
+    // The magic bytes may be split between reads
+    let mut start = Vec::new();
+    while start.len() < 4 {
+        let mut bytes = [0; 4];
+        let n = reader
+            .read(&mut bytes[..4 - start.len()])
+            .await
+            .map_err(|e| fail(read_error(e)))?;
+        if n == 0 {
+            break;
+        }
+        start.extend_from_slice(&bytes[..n]);
+    }
+
+    let compression = Compression::detect(&start);
+    let input = BufReader::new(Cursor::new(start).chain(reader));
+    let mut input = decompress(input, compression).map_err(fail)?;
+
+    let mut bytes = vec![0; READ_SIZE];
+    loop {
+        let n = match input.read(&mut bytes).await {
+            Ok(0) => return writer.eof().await,
+            Ok(n) => n,
+            Err(e) => {
+                return Err(ParseError {
+                    message: read_error(e),
+                    offset: writer.offset(),
+                })
+            }
+        };
+        writer.add_bytes(&bytes[..n]).await?;
+    }
+}
+
+impl Parser {
+    /// Parses the input from a reader as it is read, it can be compressed with any of the
+    /// formats that are enabled and in any of the encodings that JSON can be in. The offset of
+    /// an error is in the decompressed input.
+    pub async fn parse_read<R: AsyncRead + Unpin>(self, reader: R) -> Result<(), ParseError> {
+        let offsets = OffsetMap::new();
+        return self
+            .input_offsets(offsets.clone())
+            .parse_while_writing(|writer| {
+                read_decoded(reader, DecodingWriter::with_offsets(writer, offsets))
+            })
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod test_compression {
+    use super::*;
+    use crate::parser::{
+        json_path::ConsumerAction,
+        parser::test_parser::{rows, rows_consumer, LARGE_ROWS},
+    };
+    use std::{sync::Mutex, time::Duration};
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_detect_compression() {
+        assert_eq!(Compression::detect(&[0x1F, 0x8B, 8]), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(&[0x28, 0xB5, 0x2F, 0xFD]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::detect(b"BZh9"), Compression::Bzip2);
+        assert_eq!(Compression::detect(b"{}"), Compression::None);
+        assert_eq!(Compression::detect(&[0x1F]), Compression::None);
+    }
+
+    /// The input as it is and compressed with each of the formats that are enabled
+    async fn compressed_inputs(input: &str) -> Vec<(Compression, Vec<u8>)> {
+        let mut inputs = vec![(Compression::None, input.as_bytes().to_vec())];
+        #[cfg(feature = "gzip")]
+        {
+            let mut encoder = async_compression::tokio::write::GzipEncoder::new(Vec::new());
+            encoder.write_all(input.as_bytes()).await.unwrap();
+            encoder.shutdown().await.unwrap();
+            inputs.push((Compression::Gzip, encoder.into_inner()));
+        }
+        #[cfg(feature = "zstd")]
+        {
+            let mut encoder = async_compression::tokio::write::ZstdEncoder::new(Vec::new());
+            encoder.write_all(input.as_bytes()).await.unwrap();
+            encoder.shutdown().await.unwrap();
+            inputs.push((Compression::Zstd, encoder.into_inner()));
+        }
+        #[cfg(feature = "bzip2")]
+        {
+            let mut encoder = async_compression::tokio::write::BzEncoder::new(Vec::new());
+            encoder.write_all(input.as_bytes()).await.unwrap();
+            encoder.shutdown().await.unwrap();
+            inputs.push((Compression::Bzip2, encoder.into_inner()));
+        }
+        return inputs;
+    }
+
+    #[tokio::test]
+    async fn test_parse_read_compressed() {
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        for (compression, bytes) in compressed_inputs(&rows(5000)).await {
+            assert_eq!(Compression::detect(&bytes), compression);
+            IDS.lock().unwrap().clear();
+            let res = Parser::new(rows_consumer(|x, _| {
+                // Only every 1000th id is kept so that the vec stays small
+                if x % 1000 == 0 {
+                    IDS.lock().unwrap().push(x);
+                }
+                ConsumerAction::Continue
+            }))
+            .parse_read(&bytes[..])
+            .await;
+
+            assert_eq!(res, Ok(()), "{:?}", compression);
+            assert_eq!(
+                *IDS.lock().unwrap(),
+                vec![0, 1000, 2000, 3000, 4000],
+                "{:?}",
+                compression
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_read_stops_reading_after_an_error() {
+        let input = rows(LARGE_ROWS).replacen(r#""id": 1,"#, r#""id": tru,"#, 1);
+        assert!(input.len() > 1024 * 1024);
+
+        for (compression, bytes) in compressed_inputs(&input).await {
+            let res =
+                Parser::new(rows_consumer(|_, _| ConsumerAction::Continue)).parse_read(&bytes[..]);
+            let res = tokio::time::timeout(Duration::from_secs(10), res).await;
+            assert_eq!(
+                res,
+                Ok(Err(ParseError {
+                    message: "Cannot scan boolean",
+                    offset: 49
+                })),
+                "{:?}",
+                compression
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_read_stopped_by_a_consumer() {
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        for (compression, bytes) in compressed_inputs(&rows(LARGE_ROWS)).await {
+            IDS.lock().unwrap().clear();
+            let res = Parser::new(rows_consumer(|x, _| {
+                IDS.lock().unwrap().push(x);
+                match x {
+                    2 => ConsumerAction::Stop,
+                    _ => ConsumerAction::Continue,
+                }
+            }))
+            .parse_read(&bytes[..]);
+            let res = tokio::time::timeout(Duration::from_secs(10), res).await;
+
+            assert_eq!(res, Ok(Ok(())), "{:?}", compression);
+            assert_eq!(*IDS.lock().unwrap(), vec![0, 1, 2], "{:?}", compression);
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn test_parse_read_invalid_compressed_data() {
+        let mut encoder = async_compression::tokio::write::GzipEncoder::new(Vec::new());
+        encoder.write_all(rows(10).as_bytes()).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        let mut bytes = encoder.into_inner();
+
+        // The end of the stream is missing
+        let res = Parser::new(rows_consumer(|_, _| ConsumerAction::Continue))
+            .parse_read(&bytes[..bytes.len() / 2])
+            .await;
+        assert_eq!(
+            res.map_err(|x| x.message),
+            Err("The compressed input ended early")
+        );
+
+        // The checksum is wrong
+        let i = bytes.len() - 6;
+        bytes[i] ^= 0xFF;
+        let res = Parser::new(rows_consumer(|_, _| ConsumerAction::Continue))
+            .parse_read(&bytes[..])
+            .await;
+        assert_eq!(res.map_err(|x| x.message), Err("Invalid compressed data"));
+    }
+}
@@ -0,0 +1,378 @@
+use indexmap::IndexMap;
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::{
+    buffer::Buffer,
+    json_path::{ConsumerAction, JsonPrimitive, ObjectConsumer, PathContext, PathSegment},
+    json_value::JsonValue,
+    lexer::tokens::number_token::NumberToken,
+    parser::{ParseError, Parser},
+};
+
+/// Converts a scanned value into a Rust type, this is implemented by `#[derive(IncJson)]`.
+pub trait FromJsonValue: Sized {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str>;
+
+    /// The value of a field that is not in its object, which is an error unless the type is
+    /// optional.
+    fn missing() -> Result<Self, &'static str> {
+        return Err("Missing field");
+    }
+
+    /// Registers the consumers that collect the value of .key for a `StructParser`, the whole
+    /// value is collected unless it is a struct.
+    fn register_field(consumer: &mut ObjectConsumer, key: String) {
+        consumer.value(key, store_field);
+    }
+}
+
+/// A struct that can be parsed from a stream, this is implemented by `#[derive(IncJson)]`.
+pub trait IncJson: FromJsonValue {
+    /// Registers the consumers that collect the fields of the struct for a `StructParser`.
+    fn register_fields(consumer: &mut ObjectConsumer);
+}
+
+impl FromJsonValue for JsonValue {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+        return Ok(value);
+    }
+}
+
+impl FromJsonValue for String {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+        return match value {
+            JsonValue::String(x) => Ok(x),
+            _ => Err("Expected a string"),
+        };
+    }
+}
+
+impl FromJsonValue for bool {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+        return match value {
+            JsonValue::Boolean(x) => Ok(x),
+            _ => Err("Expected a boolean"),
+        };
+    }
+}
+
+impl FromJsonValue for f64 {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+        return match value {
+            JsonValue::Number(NumberToken::Integer(x)) => Ok(x as f64),
+            JsonValue::Number(NumberToken::Float(x)) => Ok(x),
+            _ => Err("Expected a number"),
+        };
+    }
+}
+
+impl FromJsonValue for f32 {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+        return Ok(f64::from_json_value(value)? as f32);
+    }
+}
+
+/// Implements `FromJsonValue` for integer types, failing if the integer does not fit.
+macro_rules! impl_from_json_value_for_integer {
+    ($($t:ty),*) => {
+        $(impl FromJsonValue for $t {
+            fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+                return match value {
+                    JsonValue::Number(NumberToken::Integer(x)) => {
+                        <$t>::try_from(x).map_err(|_| "Integer is out of range")
+                    }
+                    _ => Err("Expected an integer"),
+                };
+            }
+        })*
+    };
+}
+
+impl_from_json_value_for_integer!(i64, i32, i16, i8, u64, u32, u16, u8, usize);
+
+impl<T: FromJsonValue> FromJsonValue for Option<T> {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+        return match value {
+            JsonValue::Null => Ok(None),
+            x => Ok(Some(T::from_json_value(x)?)),
+        };
+    }
+
+    fn missing() -> Result<Self, &'static str> {
+        return Ok(None);
+    }
+
+    fn register_field(consumer: &mut ObjectConsumer, key: String) {
+        T::register_field(consumer, key);
+    }
+}
+
+impl<T: FromJsonValue> FromJsonValue for Vec<T> {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+        return match value {
+            JsonValue::Array(x) => x.into_iter().map(T::from_json_value).collect(),
+            _ => Err("Expected an array"),
+        };
+    }
+}
+
+impl<T: FromJsonValue> FromJsonValue for HashMap<String, T> {
+    fn from_json_value(value: JsonValue) -> Result<Self, &'static str> {
+        return match value {
+            JsonValue::Object(x) => x
+                .into_iter()
+                .map(|(key, x)| Ok((key, T::from_json_value(x)?)))
+                .collect(),
+            _ => Err("Expected an object"),
+        };
+    }
+}
+
+/// Gets the value of a field for a derived `FromJsonValue`.
+pub fn take_field<T: FromJsonValue>(
+    fields: &mut IndexMap<String, JsonValue>,
+    key: &str,
+) -> Result<T, &'static str> {
+    return match fields.swap_remove(key) {
+        Some(x) => T::from_json_value(x),
+        None => T::missing(),
+    };
+}
+
+/// Gets the value of a streamed field for a derived `FromJsonValue`, which is empty when its
+/// members were passed to a callback.
+pub fn take_streamed_field<T: FromJsonValue + Default>(
+    fields: &mut IndexMap<String, JsonValue>,
+    key: &str,
+) -> Result<T, &'static str> {
+    return match fields.swap_remove(key) {
+        Some(x) => T::from_json_value(x),
+        None => Ok(T::default()),
+    };
+}
+
+type StreamCallback = Box<dyn FnMut(JsonValue) -> Result<(), &'static str> + Send>;
+
+/// The fields of the struct that a `StructParser` is parsing, which is passed to the consumers
+/// that collect them as the state of the parser.
+#[derive(Default)]
+struct PartialStruct {
+    /// The fields that have been scanned, the fields of a nested struct are in an object at its
+    /// key
+    fields: IndexMap<String, JsonValue>,
+    /// The callbacks for the members of streamed fields, by the path to the field
+    streams: HashMap<Vec<PathSegment>, StreamCallback>,
+    error: Option<ParseError>,
+}
+
+impl PartialStruct {
+    /// The fields of the nested struct at the path, which are added if there are none yet
+    fn fields_at(&mut self, path: &[PathSegment]) -> Option<&mut IndexMap<String, JsonValue>> {
+        let mut fields = &mut self.fields;
+        for segment in path {
+            let PathSegment::Key(key) = segment else {
+                return None;
+            };
+            let value = fields
+                .entry(key.clone())
+                .or_insert_with(|| JsonValue::Object(IndexMap::new()));
+            fields = match value {
+                JsonValue::Object(x) => x,
+                _ => return None,
+            };
+        }
+        return Some(fields);
+    }
+
+    fn insert(&mut self, path: &[PathSegment], value: JsonValue) {
+        if let Some((PathSegment::Key(key), parent)) = path.split_last() {
+            if let Some(fields) = self.fields_at(parent) {
+                fields.insert(key.clone(), value);
+            }
+        }
+    }
+}
+
+/// The struct that the consumers are collecting, they are only called by a `StructParser`.
+fn partial<'a>(context: &PathContext<'a>) -> MutexGuard<'a, PartialStruct> {
+    return context
+        .state::<Mutex<PartialStruct>>()
+        .expect("derived consumers are only called by a StructParser")
+        .lock()
+        .unwrap();
+}
+
+/// Stores the value of a field for the `StructParser` that is running.
+pub fn store_field(value: JsonValue, context: &PathContext) -> ConsumerAction {
+    partial(context).insert(context.path(), value);
+    return ConsumerAction::Continue;
+}
+
+/// Stores a primitive in the place of a nested struct, so that converting it fails with the
+/// type that was expected.
+pub fn store_primitive(primitive: JsonPrimitive, context: &PathContext) -> ConsumerAction {
+    return store_field(JsonValue::from(primitive), context);
+}
+
+/// Adds the fields of a nested struct when its object starts, so that a struct whose fields
+/// are all optional is not missing.
+pub fn start_struct(context: &PathContext) -> ConsumerAction {
+    partial(context).fields_at(context.path());
+    return ConsumerAction::Continue;
+}
+
+/// Registers the consumers for a field that is a struct, which collect each of its fields
+/// rather than building the whole object.
+pub fn register_struct<T: IncJson>(consumer: &mut ObjectConsumer, key: String) {
+    let mut fields = ObjectConsumer::new();
+    fields.on_start(start_struct);
+    T::register_fields(&mut fields);
+    consumer
+        .primitive(key.clone(), store_primitive)
+        .object(key, &fields);
+}
+
+/// Passes a member of a streamed field to its callback, or collects it if there is no callback
+/// for the field.
+pub fn stream_member(value: JsonValue, context: &PathContext) -> ConsumerAction {
+    // The member is in the array of the field
+    let field = match context.path().split_last() {
+        Some((PathSegment::Index(_), field)) => field,
+        _ => return ConsumerAction::Continue,
+    };
+
+    let mut partial = partial(context);
+    let res = match partial.streams.get_mut(field) {
+        Some(callback) => callback(value),
+        None => {
+            if let Some((PathSegment::Key(key), parent)) = field.split_last() {
+                let members = partial
+                    .fields_at(parent)
+                    .map(|x| x.entry(key.clone()).or_insert(JsonValue::Array(Vec::new())));
+                if let Some(JsonValue::Array(x)) = members {
+                    x.push(value);
+                }
+            }
+            Ok(())
+        }
+    };
+
+    return match res {
+        Ok(()) => ConsumerAction::Continue,
+        Err(message) => {
+            partial.error = Some(ParseError {
+                message,
+                offset: context.offset(),
+            });
+            ConsumerAction::Stop
+        }
+    };
+}
+
+/// Parses a struct that derives `IncJson` from the object at the root of the input. The
+/// members of streamed fields are passed to their callbacks as soon as each has been scanned,
+/// streamed fields without a callback are collected like any other field.
+pub struct StructParser<T: IncJson> {
+    streams: HashMap<Vec<PathSegment>, StreamCallback>,
+    _struct: PhantomData<T>,
+}
+
+impl<T: IncJson> StructParser<T> {
+    pub fn new() -> Self {
+        return StructParser {
+            streams: HashMap::new(),
+            _struct: PhantomData,
+        };
+    }
+
+    /// Passes each member of the streamed field with the key to the callback rather than
+    /// adding it to the struct.
+    pub fn stream<U: FromJsonValue>(
+        mut self,
+        key: &str,
+        mut callback: impl FnMut(U) + Send + 'static,
+    ) -> Self {
+        self.streams.insert(
+            vec![PathSegment::Key(key.to_string())],
+            Box::new(move |x| {
+                callback(U::from_json_value(x)?);
+                return Ok(());
+            }),
+        );
+        return self;
+    }
+
+    pub async fn parse(self, buffer: &mut Pin<Box<&mut Buffer>>) -> Result<T, ParseError> {
+        let mut consumer = ObjectConsumer::new();
+        T::register_fields(&mut consumer);
+        let partial = Arc::new(Mutex::new(PartialStruct {
+            streams: self.streams,
+            ..Default::default()
+        }));
+
+        Parser::new(consumer)
+            .consumer_state(partial.clone())
+            .parse(buffer)
+            .await?;
+
+        let (fields, error) = {
+            let mut partial = partial.lock().unwrap();
+            (std::mem::take(&mut partial.fields), partial.error.take())
+        };
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        let offset = buffer.offset().await;
+        return T::from_json_value(JsonValue::Object(fields))
+            .map_err(|message| ParseError { message, offset });
+    }
+}
+
+#[cfg(test)]
+mod test_derive {
+    use super::*;
+
+    #[test]
+    fn test_from_json_value() {
+        assert_eq!(
+            Option::<u8>::from_json_value(JsonValue::Number(NumberToken::Integer(3))),
+            Ok(Some(3))
+        );
+        assert_eq!(Option::<u8>::from_json_value(JsonValue::Null), Ok(None));
+        assert_eq!(
+            u8::from_json_value(JsonValue::Number(NumberToken::Integer(300))),
+            Err("Integer is out of range")
+        );
+        assert_eq!(
+            f64::from_json_value(JsonValue::Number(NumberToken::Integer(2))),
+            Ok(2.0)
+        );
+        assert_eq!(
+            Vec::<String>::from_json_value(JsonValue::Array(vec![
+                JsonValue::String("a".to_string()),
+                JsonValue::Boolean(true)
+            ])),
+            Err("Expected a string")
+        );
+    }
+
+    #[test]
+    fn test_take_field() {
+        let mut fields = IndexMap::new();
+        fields.insert("a".to_string(), JsonValue::Boolean(true));
+
+        assert_eq!(take_field::<Option<bool>>(&mut fields, "b"), Ok(None));
+        assert_eq!(take_field::<bool>(&mut fields, "b"), Err("Missing field"));
+        assert_eq!(
+            take_streamed_field::<Vec<bool>>(&mut fields, "b"),
+            Ok(vec![])
+        );
+        assert_eq!(take_field::<bool>(&mut fields, "a"), Ok(true));
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+
+use super::json_path::{PathContext, PathSegment};
+
+/// How many chars of the line are shown on each side of where an error is
+const SNIPPET_CONTEXT: usize = 40;
+
+/// The line of the input that an error is on. Lines and columns count from 1 at the start of
+/// the data in the buffer, columns are in chars.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snippet {
+    pub line: usize,
+    pub column: usize,
+    /// The part of the line around the error, long lines are cut short with `...`
+    pub text: String,
+    /// The index in chars of the error in the text
+    pub caret: usize,
+}
+
+impl Snippet {
+    /// Makes a snippet from the part of the line before the error and the part from the error
+    /// to the end of the line. `truncated` is whether the start of the line is not known.
+    pub(crate) fn new(
+        line: usize,
+        column: usize,
+        before: &str,
+        after: &str,
+        truncated: bool,
+    ) -> Self {
+        // Tabs are shown as spaces so that the caret lines up
+        let before: Vec<char> = before
+            .chars()
+            .map(|x| if x == '\t' { ' ' } else { x })
+            .collect();
+        let mut text = String::new();
+        if truncated || before.len() > SNIPPET_CONTEXT {
+            text.push_str("...");
+        }
+        text.extend(&before[before.len().saturating_sub(SNIPPET_CONTEXT)..]);
+        let caret = text.chars().count();
+
+        let after = after
+            .split('\n')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('\r');
+        text.extend(
+            after
+                .chars()
+                .take(SNIPPET_CONTEXT)
+                .map(|x| if x == '\t' { ' ' } else { x }),
+        );
+        if after.chars().count() > SNIPPET_CONTEXT {
+            text.push_str("...");
+        }
+
+        return Snippet {
+            line,
+            column,
+            text,
+            caret,
+        };
+    }
+}
+
+/// A parse error with what is needed to show it to a person: the line of the input that it is
+/// on, the path to the value that it is in and a hint about what may be wrong.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: &'static str,
+    /// The byte offset from the start of the input
+    pub offset: usize,
+    pub path: Vec<PathSegment>,
+    /// `None` when the line is no longer in the buffer's history
+    pub snippet: Option<Snippet>,
+    pub hint: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = PathContext::new(&self.path, self.offset);
+        write!(f, "{} at {} (byte {})", self.message, context, self.offset)?;
+
+        let mut gutter = " ".to_string();
+        if let Some(snippet) = &self.snippet {
+            let line = snippet.line.to_string();
+            gutter = " ".repeat(line.len());
+            write!(
+                f,
+                "\n{} --> line {}, column {}",
+                gutter, snippet.line, snippet.column
+            )?;
+            write!(f, "\n{} |", gutter)?;
+            write!(f, "\n{} | {}", line, snippet.text)?;
+            write!(f, "\n{} | {}^", gutter, " ".repeat(snippet.caret))?;
+        }
+
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{} = hint: {}", gutter, hint)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod test_diagnostic {
+    use super::*;
+
+    #[test]
+    fn test_snippet_cuts_long_lines() {
+        let before = "a".repeat(50);
+        let snippet = Snippet::new(1, 51, &before, "b,\nc", false);
+        assert_eq!(snippet.text, format!("...{}b,", "a".repeat(40)));
+        assert_eq!(snippet.caret, 43);
+
+        let snippet = Snippet::new(2, 9, "\t\"a\": ", &"1".repeat(45), true);
+        assert_eq!(snippet.text, format!("... \"a\": {}...", "1".repeat(40)));
+        assert_eq!(snippet.caret, 9);
+    }
+
+    #[test]
+    fn test_display_diagnostic() {
+        let diagnostic = Diagnostic {
+            message: "Unexpected token",
+            offset: 40,
+            path: vec![PathSegment::Key("rows".to_string()), PathSegment::Index(1)],
+            snippet: Some(Snippet::new(12, 12, "  {\"id\": 2,", "}", false)),
+            hint: Some("trailing comma".to_string()),
+        };
+
+        assert_eq!(
+            diagnostic.to_string(),
+            [
+                "Unexpected token at $.rows[1] (byte 40)",
+                "   --> line 12, column 12",
+                "   |",
+                "12 |   {\"id\": 2,}",
+                "   |            ^",
+                "   = hint: trailing comma",
+            ]
+            .join("\n")
+        );
+    }
+}
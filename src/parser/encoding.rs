@@ -0,0 +1,632 @@
+use super::{buffer::BufferWriter, parser::ParseError};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+/// The encodings that JSON can be in, from RFC 4627. Only UTF-8 is allowed by RFC 8259 but
+/// the others are still seen in files written on Windows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+}
+
+/// The byte order marks, longest first so that a UTF-32LE one is not read as UTF-16LE
+const BOMS: [(&[u8], Encoding); 5] = [
+    (&[0x00, 0x00, 0xFE, 0xFF], Encoding::Utf32Be),
+    (&[0xFF, 0xFE, 0x00, 0x00], Encoding::Utf32Le),
+    (&[0xEF, 0xBB, 0xBF], Encoding::Utf8),
+    (&[0xFE, 0xFF], Encoding::Utf16Be),
+    (&[0xFF, 0xFE], Encoding::Utf16Le),
+];
+
+/// Works out the encoding from the start of the input and the length of the BOM. JSON starts
+/// with an ASCII char, so when there is no BOM the encoding is given by which of the first
+/// four bytes are null. `None` is returned if more bytes are needed, `end` is whether there
+/// are no more bytes.
+fn detect(bytes: &[u8], end: bool) -> Option<(Encoding, usize)> {
+    for (bom, encoding) in BOMS {
+        if bytes.starts_with(bom) {
+            return Some((encoding, bom.len()));
+        }
+        // The start of a longer BOM may be a shorter one once the rest of it is read
+        if !end && bom.starts_with(bytes) {
+            return None;
+        }
+    }
+    if !end && bytes.len() < 4 {
+        return None;
+    }
+
+    let nulls: Vec<bool> = bytes.iter().take(4).map(|x| *x == 0).collect();
+    let encoding = match nulls[..] {
+        [true, true, true, false] => Encoding::Utf32Be,
+        [false, true, true, true] => Encoding::Utf32Le,
+        [true, false, ..] => Encoding::Utf16Be,
+        [false, true, ..] => Encoding::Utf16Le,
+        _ => Encoding::Utf8,
+    };
+    return Some((encoding, 0));
+}
+
+/// Decodes the complete chars at the start of the bytes, returning how many bytes were used.
+/// An error has the index of the first byte that is not valid.
+fn decode_utf8(bytes: &[u8], out: &mut String) -> Result<usize, (&'static str, usize)> {
+    let valid = match std::str::from_utf8(bytes) {
+        Ok(_) => bytes.len(),
+        // The bytes at the end may be the start of a char in the next chunk
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(e) => return Err(("Invalid UTF-8", e.valid_up_to())),
+    };
+
+    // This is known safe as the bytes have just been checked
+    out.push_str(std::str::from_utf8(&bytes[..valid]).unwrap());
+    return Ok(valid);
+}
+
+fn decode_utf16(
+    bytes: &[u8],
+    unit: fn([u8; 2]) -> u16,
+    out: &mut String,
+) -> Result<usize, (&'static str, usize)> {
+    let mut i = 0;
+    while i + 2 <= bytes.len() {
+        let first = unit([bytes[i], bytes[i + 1]]) as u32;
+        match first {
+            0xD800..=0xDBFF => {
+                if i + 4 > bytes.len() {
+                    break;
+                }
+                let second = unit([bytes[i + 2], bytes[i + 3]]) as u32;
+                if !(0xDC00..=0xDFFF).contains(&second) {
+                    return Err(("Invalid UTF-16", i));
+                }
+                let c = 0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00);
+                // This is known safe as a surrogate pair is always a valid char
+                out.push(char::from_u32(c).unwrap());
+                i += 4;
+            }
+            0xDC00..=0xDFFF => return Err(("Invalid UTF-16", i)),
+            _ => {
+                // This is known safe as it is not a surrogate
+                out.push(char::from_u32(first).unwrap());
+                i += 2;
+            }
+        }
+    }
+
+    return Ok(i);
+}
+
+fn decode_utf32(
+    bytes: &[u8],
+    unit: fn([u8; 4]) -> u32,
+    out: &mut String,
+) -> Result<usize, (&'static str, usize)> {
+    let mut i = 0;
+    while i + 4 <= bytes.len() {
+        let unit = unit([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        match char::from_u32(unit) {
+            Some(c) => out.push(c),
+            None => return Err(("Invalid UTF-32", i)),
+        }
+        i += 4;
+    }
+
+    return Ok(i);
+}
+
+/// Chars that each take the same number of bytes in the text as they did in the input
+#[derive(Clone, Copy)]
+struct Run {
+    /// The offsets of the first char of the run
+    text: usize,
+    input: usize,
+    text_len: usize,
+    input_len: usize,
+}
+
+/// Where the text that a decoder has produced came from in its input, so that the offset of an
+/// error in the text can be turned back into the offset in the input. It is shared between the
+/// decoder and whatever reads the text, cloning it gives another handle to the same map. A
+/// parser that is given the map drops the runs before the text it can still report errors in.
+#[derive(Clone, Default)]
+pub struct OffsetMap {
+    runs: Arc<Mutex<VecDeque<Run>>>,
+}
+
+impl OffsetMap {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Records the text that was decoded from the input at the offset
+    fn add(&self, text: &str, mut text_offset: usize, mut input_offset: usize, encoding: Encoding) {
+        let width = match encoding {
+            Encoding::Utf8 => {
+                // Every byte of the text is a byte of the input, so one run covers all of it
+                let mut runs = self.runs.lock().unwrap();
+                if runs.is_empty() {
+                    runs.push_back(Run {
+                        text: text_offset,
+                        input: input_offset,
+                        text_len: 1,
+                        input_len: 1,
+                    });
+                }
+                return;
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => |c: char| c.len_utf16() * 2,
+            Encoding::Utf32Le | Encoding::Utf32Be => |_| 4,
+        };
+
+        let mut last = self.runs.lock().unwrap().back().copied();
+        let mut added = Vec::new();
+        for c in text.chars() {
+            let (text_len, input_len) = (c.len_utf8(), width(c));
+            let is_same = last.is_some_and(|x| x.text_len == text_len && x.input_len == input_len);
+            if !is_same {
+                let run = Run {
+                    text: text_offset,
+                    input: input_offset,
+                    text_len,
+                    input_len,
+                };
+                added.push(run);
+                last = Some(run);
+            }
+            text_offset += text_len;
+            input_offset += input_len;
+        }
+        self.runs.lock().unwrap().extend(added);
+    }
+
+    /// Drops the runs before the one that the offset is in, offsets before it are not looked
+    /// up after this.
+    pub(crate) fn discard_before(&self, offset: usize) {
+        let mut runs = self.runs.lock().unwrap();
+        let i = runs.partition_point(|x| x.text <= offset);
+        runs.drain(..i.saturating_sub(1));
+    }
+
+    /// The byte offset in the input of the char at the byte offset in the text, offsets past
+    /// the end of the text are past the end of the input by as much.
+    pub fn input_offset(&self, offset: usize) -> usize {
+        let runs = self.runs.lock().unwrap();
+        let i = runs.partition_point(|x| x.text <= offset);
+        return match i.checked_sub(1).map(|i| runs[i]) {
+            Some(run) => run.input + (offset - run.text) / run.text_len * run.input_len,
+            None => offset,
+        };
+    }
+}
+
+/// Turns bytes in any of the JSON encodings into text a chunk at a time. The encoding is
+/// detected from the first bytes and a leading BOM is dropped, chars that are split between
+/// chunks are kept until the rest of them is decoded.
+pub struct Decoder {
+    /// `None` until enough bytes have been read to tell
+    encoding: Option<Encoding>,
+    /// Bytes that are not a whole char yet
+    pending: Vec<u8>,
+    /// The byte offset from the start of the input of the first pending byte
+    offset: usize,
+    /// The number of bytes of text that have been decoded
+    text_offset: usize,
+    offsets: OffsetMap,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        return Self::with_offsets(OffsetMap::new());
+    }
+
+    /// Records where the text came from in the map, so that it can be given to a parser before
+    /// there is any text.
+    pub fn with_offsets(offsets: OffsetMap) -> Self {
+        return Decoder {
+            encoding: None,
+            pending: Vec::new(),
+            offset: 0,
+            text_offset: 0,
+            offsets,
+        };
+    }
+
+    /// The encoding of the input, once enough of it has been decoded to tell.
+    pub fn encoding(&self) -> Option<Encoding> {
+        return self.encoding;
+    }
+
+    /// The number of bytes that have been decoded.
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+
+    /// Where the decoded text came from in the input, the offsets of errors found while
+    /// parsing the text are in the text rather than the input.
+    pub fn offsets(&self) -> OffsetMap {
+        return self.offsets.clone();
+    }
+
+    /// Decodes the next chunk of bytes, the offset of an error is from the start of the input.
+    pub fn decode(&mut self, bytes: &[u8]) -> Result<String, ParseError> {
+        self.pending.extend_from_slice(bytes);
+        return self.decode_pending(false);
+    }
+
+    /// Decodes what is left at the end of the input, this fails if the last char is not whole.
+    pub fn finish(&mut self) -> Result<String, ParseError> {
+        return self.decode_pending(true);
+    }
+
+    fn decode_pending(&mut self, end: bool) -> Result<String, ParseError> {
+        let encoding = match self.encoding {
+            Some(x) => x,
+            None => match detect(&self.pending, end) {
+                Some((encoding, bom)) => {
+                    self.encoding = Some(encoding);
+                    self.pending.drain(..bom);
+                    self.offset += bom;
+                    encoding
+                }
+                None => return Ok(String::new()),
+            },
+        };
+
+        let mut text = String::new();
+        let res = match encoding {
+            Encoding::Utf8 => decode_utf8(&self.pending, &mut text),
+            Encoding::Utf16Le => decode_utf16(&self.pending, u16::from_le_bytes, &mut text),
+            Encoding::Utf16Be => decode_utf16(&self.pending, u16::from_be_bytes, &mut text),
+            Encoding::Utf32Le => decode_utf32(&self.pending, u32::from_le_bytes, &mut text),
+            Encoding::Utf32Be => decode_utf32(&self.pending, u32::from_be_bytes, &mut text),
+        };
+
+        let used = res.map_err(|(message, i)| ParseError {
+            message,
+            offset: self.offset + i,
+        })?;
+        self.offsets
+            .add(&text, self.text_offset, self.offset, encoding);
+        self.pending.drain(..used);
+        self.offset += used;
+        self.text_offset += text.len();
+
+        if end && !self.pending.is_empty() {
+            return Err(ParseError {
+                message: "Incomplete char at the end of the input",
+                offset: self.offset,
+            });
+        }
+        return Ok(text);
+    }
+}
+
+/// Adds bytes to a buffer from another task, decoding them first.
+pub struct DecodingWriter {
+    decoder: Decoder,
+    writer: BufferWriter,
+}
+
+impl DecodingWriter {
+    pub fn new(writer: BufferWriter) -> Self {
+        return Self::with_offsets(writer, OffsetMap::new());
+    }
+
+    /// Records where the text came from in the map, see `Decoder::with_offsets`.
+    pub fn with_offsets(writer: BufferWriter, offsets: OffsetMap) -> Self {
+        return DecodingWriter {
+            decoder: Decoder::with_offsets(offsets),
+            writer,
+        };
+    }
+
+    /// The encoding of the input, once enough of it has been added to tell.
+    pub fn encoding(&self) -> Option<Encoding> {
+        return self.decoder.encoding();
+    }
+
+    /// The number of bytes that have been decoded.
+    pub fn offset(&self) -> usize {
+        return self.decoder.offset();
+    }
+
+    /// Where the text in the buffer came from in the input, see `Decoder::offsets`.
+    pub fn offsets(&self) -> OffsetMap {
+        return self.decoder.offsets();
+    }
+
+    /// Decodes a chunk of bytes and adds the text to the buffer.
+    pub async fn add_bytes(&mut self, bytes: &[u8]) -> Result<(), ParseError> {
+        let text = self.decoder.decode(bytes)?;
+        return self.add_text(text).await;
+    }
+
+    /// Adds the last of the text to the buffer and ends the input.
+    pub async fn eof(&mut self) -> Result<(), ParseError> {
+        let text = self.decoder.finish()?;
+        self.add_text(text).await?;
+        self.writer.eof().await;
+        return Ok(());
+    }
+
+    async fn add_text(&mut self, text: String) -> Result<(), ParseError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let offset = self.decoder.offset();
+        return self
+            .writer
+            .add_string(text)
+            .await
+            .map_err(|message| ParseError { message, offset });
+    }
+}
+
+#[cfg(test)]
+mod test_encoding {
+    use super::*;
+    use crate::parser::{
+        buffer::Buffer, checkpoint::Checkpoint, json_path::ObjectConsumer, parser::Parser,
+    };
+    use std::borrow::BorrowMut;
+
+    fn encode(text: &str, encoding: Encoding) -> Vec<u8> {
+        return match encoding {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Encoding::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Encoding::Utf32Le => text
+                .chars()
+                .flat_map(|x| (x as u32).to_le_bytes())
+                .collect(),
+            Encoding::Utf32Be => text
+                .chars()
+                .flat_map(|x| (x as u32).to_be_bytes())
+                .collect(),
+        };
+    }
+
+    /// Decodes the bytes a byte at a time so that every char is split between chunks
+    fn decode_bytewise(bytes: &[u8]) -> (Result<String, ParseError>, Option<Encoding>) {
+        let mut decoder = Decoder::new();
+        let mut text = String::new();
+        for byte in bytes {
+            match decoder.decode(&[*byte]) {
+                Ok(x) => text.push_str(&x),
+                Err(e) => return (Err(e), decoder.encoding()),
+            }
+        }
+        let res = decoder.finish().map(|x| text + &x);
+        return (res, decoder.encoding());
+    }
+
+    #[test]
+    fn test_decode_every_encoding() {
+        let text = r#"{"a": "é🤠"}"#;
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+            Encoding::Utf32Le,
+            Encoding::Utf32Be,
+        ] {
+            let bytes = encode(text, encoding);
+            assert_eq!(
+                decode_bytewise(&bytes),
+                (Ok(text.to_string()), Some(encoding))
+            );
+
+            // With a BOM, which is not part of the text
+            let bytes = encode(&format!("\u{feff}{}", text), encoding);
+            assert_eq!(
+                decode_bytewise(&bytes),
+                (Ok(text.to_string()), Some(encoding))
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_short_input() {
+        assert_eq!(
+            decode_bytewise(b"1"),
+            (Ok("1".to_string()), Some(Encoding::Utf8))
+        );
+        assert_eq!(
+            decode_bytewise(&[b'1', 0]),
+            (Ok("1".to_string()), Some(Encoding::Utf16Le))
+        );
+        assert_eq!(
+            decode_bytewise(&[0xEF, 0xBB, 0xBF]),
+            (Ok(String::new()), Some(Encoding::Utf8))
+        );
+        assert_eq!(
+            decode_bytewise(&[]),
+            (Ok(String::new()), Some(Encoding::Utf8))
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_sequences() {
+        assert_eq!(
+            decode_bytewise(b"[\"a\xFF\"]").0,
+            Err(ParseError {
+                message: "Invalid UTF-8",
+                offset: 3
+            })
+        );
+        assert_eq!(
+            decode_bytewise(b"[\"\xC3").0,
+            Err(ParseError {
+                message: "Incomplete char at the end of the input",
+                offset: 2
+            })
+        );
+
+        // A low surrogate without a high one, after the BOM
+        let mut bytes = encode("\u{feff}[1", Encoding::Utf16Le);
+        bytes.extend_from_slice(&[0x00, 0xDC]);
+        assert_eq!(
+            decode_bytewise(&bytes).0,
+            Err(ParseError {
+                message: "Invalid UTF-16",
+                offset: 6
+            })
+        );
+
+        let mut bytes = encode("[", Encoding::Utf32Be);
+        bytes.extend_from_slice(&[0x00, 0x11, 0x00, 0x00]);
+        assert_eq!(
+            decode_bytewise(&bytes).0,
+            Err(ParseError {
+                message: "Invalid UTF-32",
+                offset: 4
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decoding_writer() {
+        let (writer, mut buf) = Buffer::channel();
+        let mut writer = DecodingWriter::new(writer);
+        let bytes = encode("\u{feff}[\"ä\"]", Encoding::Utf16Be);
+        for chunk in bytes.chunks(3) {
+            writer.add_bytes(chunk).await.unwrap();
+        }
+        writer.eof().await.unwrap();
+        assert_eq!(writer.encoding(), Some(Encoding::Utf16Be));
+
+        let mut buffer = Box::pin(buf.borrow_mut());
+        let mut text = String::new();
+        while let Ok(c) = buffer.next_char().await {
+            text.push(c);
+        }
+        assert_eq!(text, "[\"ä\"]");
+    }
+
+    #[tokio::test]
+    async fn test_error_offset_in_the_input() {
+        let text = r#"{"é🤠": 1, "a": tru}"#;
+        // The error is found after reading `tru}`
+        let text_offset = text.find('}').unwrap() + 1;
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+            Encoding::Utf32Le,
+            Encoding::Utf32Be,
+        ] {
+            let (writer, mut buf) = Buffer::channel();
+            let mut writer = DecodingWriter::new(writer);
+            let bytes = encode(&format!("\u{feff}{}", text), encoding);
+            for chunk in bytes.chunks(5) {
+                writer.add_bytes(chunk).await.unwrap();
+            }
+            writer.eof().await.unwrap();
+
+            let buffer_pinned = &mut Box::pin(buf.borrow_mut());
+            let res = Parser::new(ObjectConsumer::new())
+                .input_offsets(writer.offsets())
+                .parse(buffer_pinned)
+                .await;
+            let offset = encode(&format!("\u{feff}{}", &text[..text_offset]), encoding).len();
+            assert_eq!(
+                res,
+                Err(ParseError {
+                    message: "Cannot scan boolean",
+                    offset
+                }),
+                "{:?}",
+                encoding
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resumed_error_offset_in_the_input() {
+        static CHECKPOINT: Mutex<Option<Checkpoint>> = Mutex::new(None);
+
+        let text = r#"{"é": [1, 2], "ü": 3, "a": tru}"#;
+        let (writer, mut buf) = Buffer::channel();
+        let mut writer = DecodingWriter::new(writer);
+        writer
+            .add_bytes(&encode(text, Encoding::Utf16Le))
+            .await
+            .unwrap();
+        writer.eof().await.unwrap();
+
+        let buffer_pinned = &mut Box::pin(buf.borrow_mut());
+        let parsed = Parser::new(ObjectConsumer::new())
+            .input_offsets(writer.offsets())
+            .checkpoint_every(8, |x| {
+                CHECKPOINT.lock().unwrap().get_or_insert(x.clone());
+            })
+            .parse(buffer_pinned)
+            .await;
+        assert!(parsed.is_err());
+
+        let checkpoint = CHECKPOINT.lock().unwrap().clone().unwrap();
+        let mut buffer = Buffer::new();
+        buffer
+            .add_data(text[checkpoint.offset()..].chars().collect())
+            .await
+            .unwrap();
+        buffer.eof().await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let resumed = Parser::new(ObjectConsumer::new())
+            .input_offsets(writer.offsets())
+            .resume(&checkpoint, buffer_pinned)
+            .await;
+        assert_eq!(resumed, parsed);
+    }
+
+    #[tokio::test]
+    async fn test_offset_map_is_bounded() {
+        let offsets = OffsetMap::new();
+        let mut decoder = Decoder::with_offsets(offsets.clone());
+        decoder
+            .decode(&encode(&"\u{feff}[1, 2, 3]".repeat(1000), Encoding::Utf8))
+            .unwrap();
+        assert_eq!(offsets.runs.lock().unwrap().len(), 1);
+        assert_eq!(offsets.input_offset(10), 13);
+
+        // Every member changes the width of the chars twice
+        let text = format!("[{}1]", r#""aé", "#.repeat(20_000));
+        let (writer, mut buf) = Buffer::channel();
+        let mut writer = DecodingWriter::new(writer);
+        writer
+            .add_bytes(&encode(&text, Encoding::Utf16Le))
+            .await
+            .unwrap();
+        writer.eof().await.unwrap();
+        let offsets = writer.offsets();
+        assert!(offsets.runs.lock().unwrap().len() > 40_000);
+
+        let buffer_pinned = &mut Box::pin(buf.borrow_mut());
+        let res = Parser::new(ObjectConsumer::new())
+            .input_offsets(offsets.clone())
+            .parse(buffer_pinned)
+            .await;
+        assert!(res.is_ok());
+        assert!(offsets.runs.lock().unwrap().len() < 2_000);
+
+        // The offsets after the last token are still mapped
+        let end = text.len() - 2;
+        assert_eq!(
+            offsets.input_offset(end),
+            text.encode_utf16().count() * 2 - 4
+        );
+    }
+}
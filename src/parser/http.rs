@@ -0,0 +1,202 @@
+use super::{
+    buffer::BufferWriter,
+    encoding::{DecodingWriter, OffsetMap},
+    parser::{ParseError, Parser},
+};
+use bytes::Buf;
+use futures_util::{Stream, StreamExt};
+use http_body::Body;
+use http_body_util::BodyExt;
+
+/// Reads all of the chunks from the stream into the buffer, decoding the text as it is read.
+/// An error from the stream fails with the offset of the text that had been read before it.
+pub async fn read_stream_into_buffer<S, D, E>(
+    stream: S,
+    writer: BufferWriter,
+) -> Result<(), ParseError>
+where
+    S: Stream<Item = Result<D, E>>,
+    D: Buf,
+{
+    return read_decoded(stream, DecodingWriter::new(writer)).await;
+}
+
+async fn read_decoded<S, D, E>(stream: S, mut writer: DecodingWriter) -> Result<(), ParseError>
+where
+    S: Stream<Item = Result<D, E>>,
+    D: Buf,
+{
+    let mut stream = Box::pin(stream);
+    while let Some(chunk) = stream.next().await {
+        let mut chunk = match chunk {
+            Ok(x) => x,
+            Err(_) => {
+                return Err(ParseError {
+                    message: "Cannot read the body",
+                    offset: writer.offset(),
+                })
+            }
+        };
+
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            let n = bytes.len();
+            writer.add_bytes(bytes).await?;
+            chunk.advance(n);
+        }
+    }
+
+    return writer.eof().await;
+}
+
+impl Parser {
+    /// Parses a stream of chunks, such as the body of a HTTP response, as the chunks arrive.
+    /// The offset of an error is in the bytes of the stream.
+    pub async fn parse_stream<S, D, E>(self, stream: S) -> Result<(), ParseError>
+    where
+        S: Stream<Item = Result<D, E>>,
+        D: Buf,
+    {
+        let offsets = OffsetMap::new();
+        return self
+            .input_offsets(offsets.clone())
+            .parse_while_writing(|writer| {
+                read_decoded(stream, DecodingWriter::with_offsets(writer, offsets))
+            })
+            .await;
+    }
+
+    /// Parses a HTTP body as it arrives, trailers are ignored.
+    pub async fn parse_body<B: Body>(self, body: B) -> Result<(), ParseError> {
+        return self.parse_stream(body.into_data_stream()).await;
+    }
+}
+
+#[cfg(test)]
+mod test_http {
+    use super::*;
+    use crate::parser::{
+        json_path::{ConsumerAction, ObjectConsumer},
+        parser::test_parser::rows_consumer,
+    };
+    use bytes::Bytes;
+    use futures_util::stream::BoxStream;
+    use http_body::Frame;
+    use http_body_util::{Empty, StreamBody};
+    use hyper::{body::Incoming, server::conn::http1, service::service_fn, Request, Response};
+    use hyper_util::rt::TokioIo;
+    use std::{convert::Infallible, sync::Mutex, time::Duration};
+    use tokio::net::{TcpListener, TcpStream};
+
+    type ChunkedBody = StreamBody<BoxStream<'static, Result<Frame<Bytes>, std::io::Error>>>;
+
+    /// Serves one response with each chunk sent a little after the last one, ending with an
+    /// error if `fail` is set. The response to the request is returned.
+    async fn serve_chunks(chunks: &'static [&'static str], fail: bool) -> Response<Incoming> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |_: Request<Incoming>| async move {
+                let error = fail.then_some(None);
+                let frames = futures_util::stream::iter(chunks.iter().map(Some).chain(error)).then(
+                    |x| async move {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                        return match x {
+                            Some(x) => Ok(Frame::data(Bytes::from_static(x.as_bytes()))),
+                            None => Err(std::io::Error::other("The connection was reset")),
+                        };
+                    },
+                );
+                let body: ChunkedBody = StreamBody::new(frames.boxed());
+                return Ok::<_, Infallible>(Response::new(body));
+            });
+            // Failing the body closes the connection, which is the point of the test
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        return sender
+            .send_request(Request::new(Empty::<Bytes>::new()))
+            .await
+            .unwrap();
+    }
+
+    const CHUNKS: [&str; 4] = [
+        r#"{"rows": [{"i"#,
+        r#"d": 1}, {"id": "#,
+        "2}, {\"id\": 3",
+        "}]}",
+    ];
+
+    #[tokio::test]
+    async fn test_parse_chunked_response() {
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        let response = serve_chunks(&CHUNKS, false).await;
+        assert!(response.headers().get("content-length").is_none());
+        let res = Parser::new(rows_consumer(|x, _| {
+            IDS.lock().unwrap().push(x);
+            ConsumerAction::Continue
+        }))
+        .parse_body(response.into_body())
+        .await;
+
+        assert_eq!(res, Ok(()));
+        assert_eq!(*IDS.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_response_transport_error() {
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        let response = serve_chunks(&CHUNKS[..2], true).await;
+        let res = Parser::new(rows_consumer(|x, _| {
+            IDS.lock().unwrap().push(x);
+            ConsumerAction::Continue
+        }))
+        .parse_body(response.into_body())
+        .await;
+
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Cannot read the body",
+                offset: 28
+            })
+        );
+        assert_eq!(*IDS.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_stream() {
+        let chunks: Vec<Result<&[u8], Infallible>> = vec![Ok(br#"{"a": [1, "#), Ok(b"2]}")];
+        let res = Parser::new(ObjectConsumer::new())
+            .parse_stream(futures_util::stream::iter(chunks))
+            .await;
+        assert_eq!(res, Ok(()));
+
+        // The offset of an error is in the UTF-16 bytes rather than the decoded text
+        let bytes: Vec<u8> = "[1, tru]"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let chunks: Vec<Result<&[u8], Infallible>> = bytes.chunks(3).map(Ok).collect();
+        let res = Parser::new(ObjectConsumer::new())
+            .parse_stream(futures_util::stream::iter(chunks))
+            .await;
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Cannot scan boolean",
+                offset: 16
+            })
+        );
+    }
+}
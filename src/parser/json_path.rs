@@ -1,38 +1,407 @@
-use super::lexer::tokens::{number_token::NumberToken, string_token::StringToken};
-use std::collections::HashMap;
+use super::{
+    base64::{Base64Alphabet, Base64Consumer, Base64Writer},
+    json_value::JsonValue,
+    lexer::tokens::{number_token::NumberToken, string_token::StringToken},
+};
+use regex::Regex;
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    fmt,
+};
 
+/// Strings are borrowed from the buffer when possible, use `StringToken::as_string` to own it.
 #[derive(Debug)]
-pub enum JsonPrimitive {
-    String(StringToken),
+pub enum JsonPrimitive<'a> {
+    String(StringToken<'a>),
     Number(NumberToken),
     Boolean(bool),
     Null,
 }
 
-pub type PrimitiveConsumer = fn(primitive: JsonPrimitive);
+/// Returned by consumers to control what the parser does next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConsumerAction {
+    /// Keep parsing as normal
+    #[default]
+    Continue,
+    /// Skip over the rest of the object or array that the value is in, no more consumers will
+    /// be called for anything inside of it.
+    SkipSubtree,
+    /// Stop parsing, the parser returns early without reading the rest of the input.
+    Stop,
+}
+
+/// A key or array index in the path to a value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Where the value that a consumer is called for is in the input.
+#[derive(Clone, Copy, Debug)]
+pub struct PathContext<'a> {
+    path: &'a [PathSegment],
+    offset: usize,
+    state: Option<&'a (dyn Any + Send + Sync)>,
+}
+
+impl<'a> PathContext<'a> {
+    pub(crate) fn new(path: &'a [PathSegment], offset: usize) -> Self {
+        return PathContext {
+            path,
+            offset,
+            state: None,
+        };
+    }
+
+    pub(crate) fn with_state(mut self, state: Option<&'a (dyn Any + Send + Sync)>) -> Self {
+        self.state = state;
+        return self;
+    }
+
+    /// The keys and array indices from the root of the document to the value
+    pub fn path(&self) -> &'a [PathSegment] {
+        return self.path;
+    }
+
+    /// The key of the value if it is in an object
+    pub fn key(&self) -> Option<&'a str> {
+        return match self.path.last() {
+            Some(PathSegment::Key(x)) => Some(x),
+            _ => None,
+        };
+    }
+
+    /// The index of the value if it is in an array
+    pub fn index(&self) -> Option<usize> {
+        return match self.path.last() {
+            Some(PathSegment::Index(x)) => Some(*x),
+            _ => None,
+        };
+    }
+
+    /// How many objects and arrays the value is inside of
+    pub fn depth(&self) -> usize {
+        return self.path.len();
+    }
+
+    /// The byte offset of the first char of the value from the start of the input
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+
+    /// The state that was given to the parser with `Parser::consumer_state()`, if it has the
+    /// type `T`
+    pub fn state<T: Any>(&self) -> Option<&'a T> {
+        return self.state?.downcast_ref();
+    }
+}
+
+/// Formats the path like `$.owner.id` or `$.friends[0]["first name"]`.
+impl fmt::Display for PathContext<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "$")?;
+        for segment in self.path {
+            match segment {
+                PathSegment::Key(x) if x.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+                    write!(f, ".{}", x)?
+                }
+                PathSegment::Key(x) => write!(f, "[{:?}]", x)?,
+                PathSegment::Index(x) => write!(f, "[{}]", x)?,
+            }
+        }
+        return Ok(());
+    }
+}
+
+pub type PrimitiveConsumer = fn(primitive: JsonPrimitive, context: &PathContext) -> ConsumerAction;
+
+/// The names of the types of JSON value, for reporting what was found when a value has the
+/// wrong type.
+pub(crate) fn type_name(primitive: &JsonPrimitive) -> &'static str {
+    return match primitive {
+        JsonPrimitive::String(_) => "string",
+        JsonPrimitive::Number(_) => "number",
+        JsonPrimitive::Boolean(_) => "boolean",
+        JsonPrimitive::Null => "null",
+    };
+}
+
+/// A value did not have the type that its consumer was registered for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypeMismatch {
+    /// The error message, like "Expected a string"
+    pub expected: &'static str,
+    /// The type of the value, like "number" or "object"
+    pub found: &'static str,
+}
+
+/// Called instead of failing when a value has the wrong type for its typed consumer.
+pub type TypeMismatchHandler = fn(mismatch: TypeMismatch, context: &PathContext) -> ConsumerAction;
+
+/// A primitive consumer that is only called with values of one type, the parser fails with an
+/// error at the value when it has any other type.
+#[derive(Clone, Copy)]
+pub enum TypedPrimitiveConsumer {
+    String(fn(String, &PathContext) -> ConsumerAction),
+    I64(fn(i64, &PathContext) -> ConsumerAction),
+    /// Integers are converted to floats
+    F64(fn(f64, &PathContext) -> ConsumerAction),
+    Bool(fn(bool, &PathContext) -> ConsumerAction),
+    /// Called with `None` when the value is null
+    NullableString(fn(Option<String>, &PathContext) -> ConsumerAction),
+}
+
+impl TypedPrimitiveConsumer {
+    pub(crate) fn mismatch(&self, found: &'static str) -> TypeMismatch {
+        let expected = match self {
+            Self::String(_) => "Expected a string",
+            Self::I64(_) => "Expected an integer",
+            Self::F64(_) => "Expected a number",
+            Self::Bool(_) => "Expected a boolean",
+            Self::NullableString(_) => "Expected a string or null",
+        };
+        return TypeMismatch { expected, found };
+    }
+
+    /// Calls the consumer if the primitive has the type that it wants.
+    pub(crate) fn call(
+        &self,
+        primitive: JsonPrimitive,
+        context: &PathContext,
+    ) -> Result<ConsumerAction, TypeMismatch> {
+        return match (self, primitive) {
+            (Self::String(f), JsonPrimitive::String(x)) => Ok(f(x.as_string(), context)),
+            (Self::I64(f), JsonPrimitive::Number(NumberToken::Integer(x))) => Ok(f(x, context)),
+            (Self::F64(f), JsonPrimitive::Number(NumberToken::Integer(x))) => {
+                Ok(f(x as f64, context))
+            }
+            (Self::F64(f), JsonPrimitive::Number(NumberToken::Float(x))) => Ok(f(x, context)),
+            (Self::Bool(f), JsonPrimitive::Boolean(x)) => Ok(f(x, context)),
+            (Self::NullableString(f), JsonPrimitive::String(x)) => {
+                Ok(f(Some(x.as_string()), context))
+            }
+            (Self::NullableString(f), JsonPrimitive::Null) => Ok(f(None, context)),
+            (_, x) => Err(self.mismatch(type_name(&x))),
+        };
+    }
+}
+
+/// A piece of the verbatim source text of a captured value.
+#[derive(Debug, PartialEq)]
+pub enum RawFragment<'a> {
+    /// The next run of characters, the value may be split over many of these as it is read
+    /// from the buffer one chunk at a time.
+    Chunk(&'a str),
+    /// The value has been fully read and validated, no more chunks will follow.
+    End,
+}
+
+/// Once a chunk returns anything other than `ConsumerAction::Continue` no more fragments of
+/// the value are passed to the consumer, the action is taken once the value has been read.
+pub type RawConsumer = fn(fragment: RawFragment, context: &PathContext) -> ConsumerAction;
+
+pub type ValueConsumer = fn(value: JsonValue, context: &PathContext) -> ConsumerAction;
+
+/// A decoded piece of a string value.
+#[derive(Debug, PartialEq)]
+pub enum StringFragment<'a> {
+    /// The next part of the string, escape sequences have been decoded.
+    Chunk(&'a str),
+    /// The closing quote has been read, no more chunks will follow.
+    End,
+}
+
+/// Once a chunk returns anything other than `ConsumerAction::Continue` no more fragments of
+/// the string are passed to the consumer. If it was `ConsumerAction::Stop` then the rest of the
+/// string is not read.
+pub type StringChunkConsumer =
+    fn(fragment: StringFragment, context: &PathContext) -> ConsumerAction;
+
+/// Like `PrimitiveConsumer` but also passed the key that the value is for.
+pub type KeyedPrimitiveConsumer =
+    fn(key: &str, primitive: JsonPrimitive, context: &PathContext) -> ConsumerAction;
 
+/// Like `ValueConsumer` but also passed the key that the value is for.
+pub type KeyedValueConsumer =
+    fn(key: &str, value: JsonValue, context: &PathContext) -> ConsumerAction;
+
+/// Called when an object or array starts.
+pub type StartHook = fn(context: &PathContext) -> ConsumerAction;
+
+/// Called when an object or array ends with the number of keys or members that it had.
+pub type EndHook = fn(count: usize, context: &PathContext) -> ConsumerAction;
+
+/// An object did not have the shape that its `ObjectConsumer` describes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The required keys that the object did not have, in the order they were required
+    MissingKeys(Vec<String>),
+    /// A key that no consumer is registered for, when unknown keys are denied
+    UnknownKey(String),
+}
+
+impl ValidationError {
+    /// The error message that parsing fails with when there is no handler
+    pub fn message(&self) -> &'static str {
+        return match self {
+            ValidationError::MissingKeys(_) => "Missing required key",
+            ValidationError::UnknownKey(_) => "Unknown key",
+        };
+    }
+}
+
+/// Called instead of failing when an object does not have the shape that its consumer
+/// describes. The context is for the object when keys are missing, and for the key when it is
+/// unknown.
+pub type ValidationHandler = fn(error: ValidationError, context: &PathContext) -> ConsumerAction;
+
+/// Which keys of an object a wildcard consumer is for.
+#[derive(Clone, Debug)]
+pub enum KeyPattern {
+    Any,
+    Prefix(String),
+    /// Matches if the regex matches any part of the key, use `^` and `$` to match all of it
+    Regex(Regex),
+}
+
+impl KeyPattern {
+    pub fn matches(&self, key: &str) -> bool {
+        return match self {
+            KeyPattern::Any => true,
+            KeyPattern::Prefix(x) => key.starts_with(x.as_str()),
+            KeyPattern::Regex(x) => x.is_match(key),
+        };
+    }
+}
+
+/// A consumer for the values of keys that are not known ahead of time.
+#[derive(Clone)]
+pub enum KeyedConsumer {
+    PrimitiveConsumer(KeyedPrimitiveConsumer),
+    ValueConsumer(KeyedValueConsumer),
+    ObjectConsumer(ObjectConsumer),
+}
+
+/// Consumes the members of an array.
 #[derive(Clone)]
 pub enum UnknownConsumer {
     PrimitiveConsumer(PrimitiveConsumer),
     ObjectConsumer(ObjectConsumer),
+    /// Each member is built into a `JsonValue` which is passed to the consumer once the end of
+    /// it has been scanned
+    ValueConsumer(ValueConsumer),
+    /// The members are arrays, the consumer is for each of their members. The hooks are called
+    /// when each of the member arrays starts and ends.
+    ArrayConsumer(Box<UnknownConsumer>, Option<StartHook>, Option<EndHook>),
+    /// Picks the consumer by whether the member is a primitive, object or array
+    ByType(Box<TypedConsumer>),
+    /// The consumer for each index of a tuple-like array, the members after the last of these
+    /// use the second consumer if there is one.
+    Tuple(Vec<UnknownConsumer>, Option<Box<UnknownConsumer>>),
+}
+
+/// Consumers for each type that a member of an array can be.
+#[derive(Clone)]
+pub struct TypedConsumer {
+    primitive: Option<PrimitiveConsumer>,
+    object: Option<ObjectConsumer>,
+    /// Called for each member of the member when it is an array
+    array: Option<UnknownConsumer>,
+}
+
+impl TypedConsumer {
+    pub fn new() -> Self {
+        return TypedConsumer {
+            primitive: None,
+            object: None,
+            array: None,
+        };
+    }
+
+    pub fn primitive(self: &mut Self, consumer: PrimitiveConsumer) -> &mut Self {
+        self.primitive = Some(consumer);
+        return self;
+    }
+
+    pub fn object(self: &mut Self, consumer: &ObjectConsumer) -> &mut Self {
+        self.object = Some(consumer.clone());
+        return self;
+    }
+
+    pub fn array(self: &mut Self, consumer: UnknownConsumer) -> &mut Self {
+        self.array = Some(consumer);
+        return self;
+    }
+
+    pub(crate) fn primitive_consumer(&self) -> Option<&PrimitiveConsumer> {
+        return self.primitive.as_ref();
+    }
+
+    pub(crate) fn object_consumer(&self) -> Option<&ObjectConsumer> {
+        return self.object.as_ref();
+    }
+
+    pub(crate) fn array_consumer(&self) -> Option<&UnknownConsumer> {
+        return self.array.as_ref();
+    }
 }
 
 #[derive(Clone)]
 pub struct ObjectConsumer {
     /// Called when .key is a primitive
     primitive_consumers: HashMap<String, PrimitiveConsumer>,
+    /// Called when .key is a primitive of the type that the consumer wants
+    typed_consumers: HashMap<String, TypedPrimitiveConsumer>,
     /// Called when .key is an object
     object_consumers: HashMap<String, ObjectConsumer>,
     /// Called when .key is an array, and for each member of the array
     array_consumers: HashMap<String, UnknownConsumer>,
+    /// Called with the original text of .key, whatever type it is
+    raw_consumers: HashMap<String, RawConsumer>,
+    /// Called with the whole of .key once it has been scanned
+    value_consumers: HashMap<String, ValueConsumer>,
+    /// Called with each part of .key as it is scanned when it is a string
+    string_chunk_consumers: HashMap<String, StringChunkConsumer>,
+    /// Decodes .key as base64 into a writer when it is a string
+    base64_consumers: HashMap<String, Base64Consumer>,
+    /// Called for the keys that match the pattern, the first match is used
+    wildcard_consumers: Vec<(KeyPattern, KeyedConsumer)>,
+    /// Called for the keys that nothing else is registered for
+    fallback_consumer: Option<Box<KeyedConsumer>>,
+    /// Called when the object that this consumes starts and ends
+    start_hook: Option<StartHook>,
+    end_hook: Option<EndHook>,
+    /// Called when .key is an array and it starts or ends
+    array_start_hooks: HashMap<String, StartHook>,
+    array_end_hooks: HashMap<String, EndHook>,
+    /// Keys that the object must have, checked when it ends
+    required_keys: Vec<String>,
+    /// Whether keys that nothing is registered for are an error
+    deny_unknown_keys: bool,
 }
 
 impl ObjectConsumer {
     pub fn new() -> Self {
         return Self {
             primitive_consumers: HashMap::new(),
+            typed_consumers: HashMap::new(),
             object_consumers: HashMap::new(),
             array_consumers: HashMap::new(),
+            raw_consumers: HashMap::new(),
+            value_consumers: HashMap::new(),
+            string_chunk_consumers: HashMap::new(),
+            base64_consumers: HashMap::new(),
+            wildcard_consumers: Vec::new(),
+            fallback_consumer: None,
+            start_hook: None,
+            end_hook: None,
+            array_start_hooks: HashMap::new(),
+            array_end_hooks: HashMap::new(),
+            required_keys: Vec::new(),
+            deny_unknown_keys: false,
         };
     }
 
@@ -41,6 +410,61 @@ impl ObjectConsumer {
         return self;
     }
 
+    /// Called when .key is a string, the parser fails if it is any other type.
+    pub fn string(
+        self: &mut Self,
+        key: String,
+        consumer: fn(String, &PathContext) -> ConsumerAction,
+    ) -> &mut Self {
+        self.typed_consumers
+            .insert(key, TypedPrimitiveConsumer::String(consumer));
+        return self;
+    }
+
+    /// Called when .key is an integer, the parser fails if it is any other type.
+    pub fn i64(
+        self: &mut Self,
+        key: String,
+        consumer: fn(i64, &PathContext) -> ConsumerAction,
+    ) -> &mut Self {
+        self.typed_consumers
+            .insert(key, TypedPrimitiveConsumer::I64(consumer));
+        return self;
+    }
+
+    /// Called when .key is a number, the parser fails if it is any other type.
+    pub fn f64(
+        self: &mut Self,
+        key: String,
+        consumer: fn(f64, &PathContext) -> ConsumerAction,
+    ) -> &mut Self {
+        self.typed_consumers
+            .insert(key, TypedPrimitiveConsumer::F64(consumer));
+        return self;
+    }
+
+    /// Called when .key is a boolean, the parser fails if it is any other type.
+    pub fn bool(
+        self: &mut Self,
+        key: String,
+        consumer: fn(bool, &PathContext) -> ConsumerAction,
+    ) -> &mut Self {
+        self.typed_consumers
+            .insert(key, TypedPrimitiveConsumer::Bool(consumer));
+        return self;
+    }
+
+    /// Called when .key is a string or null, the parser fails if it is any other type.
+    pub fn nullable_string(
+        self: &mut Self,
+        key: String,
+        consumer: fn(Option<String>, &PathContext) -> ConsumerAction,
+    ) -> &mut Self {
+        self.typed_consumers
+            .insert(key, TypedPrimitiveConsumer::NullableString(consumer));
+        return self;
+    }
+
     pub fn object(self: &mut Self, key: String, consumer: &ObjectConsumer) -> &mut Self {
         self.object_consumers.insert(key, consumer.clone());
         return self;
@@ -50,13 +474,197 @@ impl ObjectConsumer {
         self.array_consumers.insert(key, consumer);
         return self;
     }
+
+    /// The value of .key is validated but not decoded, instead its exact source text is passed
+    /// to the consumer as it is read. Other consumers for .key are not called.
+    pub fn raw(self: &mut Self, key: String, consumer: RawConsumer) -> &mut Self {
+        self.raw_consumers.insert(key, consumer);
+        return self;
+    }
+
+    /// The value of .key is built into a `JsonValue` which is passed to the consumer once the
+    /// end of it has been scanned. Other consumers for .key are not called.
+    pub fn value(self: &mut Self, key: String, consumer: ValueConsumer) -> &mut Self {
+        self.value_consumers.insert(key, consumer);
+        return self;
+    }
+
+    /// When .key is a string it is passed to the consumer in parts as it is scanned rather
+    /// than all at once, so that very long strings do not have to be stored.
+    pub fn string_chunks(self: &mut Self, key: String, consumer: StringChunkConsumer) -> &mut Self {
+        self.string_chunk_consumers.insert(key, consumer);
+        return self;
+    }
+
+    /// When .key is a string it is decoded as base64 as it is scanned, the bytes are written to
+    /// the writer which is flushed at the end of the string. Parsing fails if it is not valid
+    /// base64.
+    pub fn base64(
+        self: &mut Self,
+        key: String,
+        alphabet: Base64Alphabet,
+        writer: Base64Writer,
+    ) -> &mut Self {
+        self.base64_consumers
+            .insert(key, Base64Consumer { alphabet, writer });
+        return self;
+    }
+
+    /// Registers a consumer for every key that matches the pattern, which is passed the key
+    /// with the value. Consumers registered for an exact key take precedence over this, and if
+    /// many patterns match a key only the one registered first is used.
+    pub fn matching(self: &mut Self, pattern: KeyPattern, consumer: KeyedConsumer) -> &mut Self {
+        self.wildcard_consumers.push((pattern, consumer));
+        return self;
+    }
+
+    /// Registers a consumer for every key that no other consumer is registered for.
+    pub fn fallback(self: &mut Self, consumer: KeyedConsumer) -> &mut Self {
+        self.fallback_consumer = Some(Box::new(consumer));
+        return self;
+    }
+
+    /// Called when the object that this consumes starts, before any of its keys are scanned.
+    pub fn on_start(self: &mut Self, hook: StartHook) -> &mut Self {
+        self.start_hook = Some(hook);
+        return self;
+    }
+
+    /// Called when the object that this consumes ends with the number of keys that it had.
+    pub fn on_end(self: &mut Self, hook: EndHook) -> &mut Self {
+        self.end_hook = Some(hook);
+        return self;
+    }
+
+    /// Called when .key is an array and it starts, before any of its members are scanned.
+    pub fn on_array_start(self: &mut Self, key: String, hook: StartHook) -> &mut Self {
+        self.array_start_hooks.insert(key, hook);
+        return self;
+    }
+
+    /// Called when .key is an array and it ends with the number of members that it had.
+    pub fn on_array_end(self: &mut Self, key: String, hook: EndHook) -> &mut Self {
+        self.array_end_hooks.insert(key, hook);
+        return self;
+    }
+
+    /// The object must have .key, whether or not a consumer is registered for it. Missing keys
+    /// are reported when the object ends.
+    pub fn require(self: &mut Self, key: String) -> &mut Self {
+        if !self.required_keys.contains(&key) {
+            self.required_keys.push(key);
+        }
+        return self;
+    }
+
+    /// Keys that are not required and that no consumer is registered for are reported as
+    /// unknown when they are scanned.
+    pub fn deny_unknown_keys(self: &mut Self, deny_unknown_keys: bool) -> &mut Self {
+        self.deny_unknown_keys = deny_unknown_keys;
+        return self;
+    }
+
+    pub(crate) fn required_keys(&self) -> &[String] {
+        return &self.required_keys;
+    }
+
+    pub(crate) fn denies_unknown_keys(&self) -> bool {
+        return self.deny_unknown_keys;
+    }
+
+    pub(crate) fn primitive_consumer(&self, key: &str) -> Option<&PrimitiveConsumer> {
+        return self.primitive_consumers.get(key);
+    }
+
+    pub(crate) fn typed_consumer(&self, key: &str) -> Option<&TypedPrimitiveConsumer> {
+        return self.typed_consumers.get(key);
+    }
+
+    pub(crate) fn object_consumer(&self, key: &str) -> Option<&ObjectConsumer> {
+        return self.object_consumers.get(key);
+    }
+
+    pub(crate) fn array_consumer(&self, key: &str) -> Option<&UnknownConsumer> {
+        return self.array_consumers.get(key);
+    }
+
+    pub(crate) fn raw_consumer(&self, key: &str) -> Option<&RawConsumer> {
+        return self.raw_consumers.get(key);
+    }
+
+    pub(crate) fn value_consumer(&self, key: &str) -> Option<&ValueConsumer> {
+        return self.value_consumers.get(key);
+    }
+
+    pub(crate) fn string_chunk_consumer(&self, key: &str) -> Option<&StringChunkConsumer> {
+        return self.string_chunk_consumers.get(key);
+    }
+
+    pub(crate) fn base64_consumer(&self, key: &str) -> Option<&Base64Consumer> {
+        return self.base64_consumers.get(key);
+    }
+
+    pub(crate) fn start_hook(&self) -> Option<&StartHook> {
+        return self.start_hook.as_ref();
+    }
+
+    pub(crate) fn end_hook(&self) -> Option<&EndHook> {
+        return self.end_hook.as_ref();
+    }
+
+    pub(crate) fn array_start_hook(&self, key: &str) -> Option<&StartHook> {
+        return self.array_start_hooks.get(key);
+    }
+
+    pub(crate) fn array_end_hook(&self, key: &str) -> Option<&EndHook> {
+        return self.array_end_hooks.get(key);
+    }
+
+    /// The wildcard or fallback consumer for .key, this should only be used when there are no
+    /// consumers registered for exactly .key.
+    pub(crate) fn keyed_consumer(&self, key: &str) -> Option<&KeyedConsumer> {
+        return self
+            .wildcard_consumers
+            .iter()
+            .find(|(pattern, _)| pattern.matches(key))
+            .map(|(_, consumer)| consumer)
+            .or(self.fallback_consumer.as_deref());
+    }
+
+    /// The number of paths that consumers have to be called for before every registered value
+    /// has been seen. A key with more than one consumer counts once, and the consumers for the
+    /// members of an array count once for the whole array. A key with an object consumer and
+    /// other consumers also counts once, as only one of them is called for its value. Any
+    /// number of keys can match a wildcard or fallback consumer, so then it is never complete.
+    pub(crate) fn required_calls(&self) -> usize {
+        if !self.wildcard_consumers.is_empty() || self.fallback_consumer.is_some() {
+            return usize::MAX;
+        }
+
+        let keys = self
+            .primitive_consumers
+            .keys()
+            .chain(self.typed_consumers.keys())
+            .chain(self.raw_consumers.keys())
+            .chain(self.value_consumers.keys())
+            .chain(self.string_chunk_consumers.keys())
+            .chain(self.base64_consumers.keys())
+            .chain(self.array_consumers.keys())
+            .collect::<HashSet<_>>();
+        return self
+            .object_consumers
+            .iter()
+            .filter(|(key, _)| !keys.contains(key))
+            .map(|(_, x)| x.required_calls())
+            .fold(keys.len(), usize::saturating_add);
+    }
 }
 
 #[cfg(test)]
 mod test_string_token {
     use super::*;
 
-    fn example_primitive_consumer(primitive: JsonPrimitive) {
+    fn example_primitive_consumer(primitive: JsonPrimitive, _: &PathContext) -> ConsumerAction {
         match primitive {
             JsonPrimitive::String(x) => println!("{}", x.as_string()),
             JsonPrimitive::Number(x) => match x {
@@ -66,15 +674,17 @@ mod test_string_token {
             JsonPrimitive::Boolean(x) => println!("{}", x),
             JsonPrimitive::Null => println!("null"),
         }
+        return ConsumerAction::Continue;
     }
 
     #[test]
     fn example_json_object_consumer() {
         ObjectConsumer::new()
             .primitive("id".to_string(), example_primitive_consumer)
-            .primitive("created".to_string(), |x| match x {
+            .primitive("created".to_string(), |x, _| match x {
                 JsonPrimitive::String(x) => {
-                    println!("Do something with the date {}", x.as_string())
+                    println!("Do something with the date {}", x.as_string());
+                    ConsumerAction::Continue
                 }
                 _ => panic!("Oh no!"),
             })
@@ -89,4 +699,79 @@ mod test_string_token {
                 UnknownConsumer::PrimitiveConsumer(example_primitive_consumer),
             );
     }
+
+    #[test]
+    fn test_required_calls() {
+        let json_path = ObjectConsumer::new()
+            .primitive("id".to_string(), example_primitive_consumer)
+            .object(
+                "owner".to_string(),
+                ObjectConsumer::new()
+                    .primitive("id".to_string(), example_primitive_consumer)
+                    .raw("name".to_string(), |_, _| ConsumerAction::Continue),
+            )
+            .array(
+                "friends".to_string(),
+                UnknownConsumer::ObjectConsumer(
+                    ObjectConsumer::new()
+                        .primitive("id".to_string(), example_primitive_consumer)
+                        .primitive("name".to_string(), example_primitive_consumer)
+                        .clone(),
+                ),
+            )
+            .clone();
+
+        assert_eq!(json_path.required_calls(), 4);
+    }
+
+    #[test]
+    fn test_required_calls_counts_keys_once() {
+        let json_path = ObjectConsumer::new()
+            .primitive("id".to_string(), example_primitive_consumer)
+            .raw("id".to_string(), |_, _| ConsumerAction::Continue)
+            .array(
+                "id".to_string(),
+                UnknownConsumer::PrimitiveConsumer(example_primitive_consumer),
+            )
+            .primitive("name".to_string(), example_primitive_consumer)
+            .clone();
+
+        assert_eq!(json_path.required_calls(), 2);
+    }
+
+    #[test]
+    fn test_key_patterns() {
+        assert!(KeyPattern::Any.matches(""));
+        assert!(KeyPattern::Prefix("u".to_string()).matches("u123"));
+        assert!(!KeyPattern::Prefix("u".to_string()).matches("id"));
+        assert!(KeyPattern::Regex(Regex::new("^u[0-9]+$").unwrap()).matches("u123"));
+        assert!(!KeyPattern::Regex(Regex::new("^u[0-9]+$").unwrap()).matches("u12a"));
+    }
+
+    #[test]
+    fn test_keyed_consumer_order() {
+        let json_path = ObjectConsumer::new()
+            .matching(
+                KeyPattern::Prefix("u".to_string()),
+                KeyedConsumer::ValueConsumer(|_, _, _| ConsumerAction::Continue),
+            )
+            .matching(
+                KeyPattern::Any,
+                KeyedConsumer::ObjectConsumer(ObjectConsumer::new()),
+            )
+            .fallback(KeyedConsumer::PrimitiveConsumer(|_, _, _| {
+                ConsumerAction::Continue
+            }))
+            .clone();
+
+        assert!(matches!(
+            json_path.keyed_consumer("u1"),
+            Some(KeyedConsumer::ValueConsumer(_))
+        ));
+        assert!(matches!(
+            json_path.keyed_consumer("id"),
+            Some(KeyedConsumer::ObjectConsumer(_))
+        ));
+        assert_eq!(json_path.required_calls(), usize::MAX);
+    }
 }
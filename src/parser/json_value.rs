@@ -0,0 +1,223 @@
+use super::{json_path::JsonPrimitive, lexer::tokens::number_token::NumberToken};
+use indexmap::IndexMap;
+use std::fmt;
+
+/// An owned JSON value, objects keep their keys in the order that they were read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Object(IndexMap<String, JsonValue>),
+    Array(Vec<JsonValue>),
+    String(String),
+    Number(NumberToken),
+    Boolean(bool),
+    Null,
+}
+
+impl JsonValue {
+    /// Gets the value of .key if this is an object
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        return match self {
+            JsonValue::Object(x) => x.get(key),
+            _ => None,
+        };
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        return match self {
+            JsonValue::String(x) => Some(x.as_str()),
+            _ => None,
+        };
+    }
+}
+
+/// Writes a string as a JSON string literal.
+fn write_json_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    return write!(f, "\"");
+}
+
+/// Writes the value as compact JSON.
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonValue::Object(x) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in x.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_json_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")?;
+            }
+            JsonValue::Array(x) => {
+                write!(f, "[")?;
+                for (i, value) in x.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")?;
+            }
+            JsonValue::String(x) => write_json_string(f, x)?,
+            JsonValue::Number(NumberToken::Integer(x)) => write!(f, "{}", x)?,
+            // Debug keeps the decimal point so that it is read back as a float
+            JsonValue::Number(NumberToken::Float(x)) => write!(f, "{:?}", x)?,
+            JsonValue::Boolean(x) => write!(f, "{}", x)?,
+            JsonValue::Null => write!(f, "null")?,
+        }
+        return Ok(());
+    }
+}
+
+impl From<JsonPrimitive<'_>> for JsonValue {
+    fn from(primitive: JsonPrimitive<'_>) -> Self {
+        return match primitive {
+            JsonPrimitive::String(x) => JsonValue::String(x.as_string()),
+            JsonPrimitive::Number(x) => JsonValue::Number(x),
+            JsonPrimitive::Boolean(x) => JsonValue::Boolean(x),
+            JsonPrimitive::Null => JsonValue::Null,
+        };
+    }
+}
+
+/// An object or array that is still being scanned
+enum PartialValue {
+    /// The key is for the next value that is added
+    Object(IndexMap<String, JsonValue>, Option<String>),
+    Array(Vec<JsonValue>),
+}
+
+/// Builds a `JsonValue` from the values scanned by the parser, the value is returned once the
+/// end of it has been scanned.
+pub(crate) struct ValueBuilder {
+    stack: Vec<PartialValue>,
+}
+
+impl ValueBuilder {
+    pub fn new() -> Self {
+        return ValueBuilder { stack: Vec::new() };
+    }
+
+    pub fn key(&mut self, key: String) {
+        if let Some(PartialValue::Object(_, next_key)) = self.stack.last_mut() {
+            *next_key = Some(key);
+        }
+    }
+
+    pub fn start_object(&mut self) {
+        self.stack.push(PartialValue::Object(IndexMap::new(), None));
+    }
+
+    pub fn start_array(&mut self) {
+        self.stack.push(PartialValue::Array(Vec::new()));
+    }
+
+    /// Ends the current object or array
+    pub fn end(&mut self) -> Option<JsonValue> {
+        let value = match self.stack.pop() {
+            Some(PartialValue::Object(x, _)) => JsonValue::Object(x),
+            Some(PartialValue::Array(x)) => JsonValue::Array(x),
+            None => return None,
+        };
+
+        return self.value(value);
+    }
+
+    /// Adds a value to the current object or array, the value is returned if it is not inside
+    /// of one.
+    pub fn value(&mut self, value: JsonValue) -> Option<JsonValue> {
+        match self.stack.last_mut() {
+            None => return Some(value),
+            Some(PartialValue::Object(x, next_key)) => {
+                if let Some(key) = next_key.take() {
+                    x.insert(key, value);
+                }
+            }
+            Some(PartialValue::Array(x)) => x.push(value),
+        };
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod test_json_value {
+    use super::*;
+
+    #[test]
+    fn test_builder_nested_values() {
+        let mut builder = ValueBuilder::new();
+        builder.start_object();
+        builder.key("b".to_string());
+        assert!(builder.value(JsonValue::Null).is_none());
+        builder.key("a".to_string());
+        builder.start_array();
+        assert!(builder.value(JsonValue::Boolean(true)).is_none());
+        builder.start_object();
+        assert!(builder.end().is_none());
+        assert!(builder.end().is_none());
+
+        let value = builder.end().unwrap();
+        let mut expected = IndexMap::new();
+        expected.insert("b".to_string(), JsonValue::Null);
+        expected.insert(
+            "a".to_string(),
+            JsonValue::Array(vec![
+                JsonValue::Boolean(true),
+                JsonValue::Object(IndexMap::new()),
+            ]),
+        );
+        assert_eq!(value, JsonValue::Object(expected));
+
+        let keys: Vec<&String> = match &value {
+            JsonValue::Object(x) => x.keys().collect(),
+            _ => panic!("Expected an object"),
+        };
+        assert_eq!(keys, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_builder_primitive_is_returned() {
+        let mut builder = ValueBuilder::new();
+        assert_eq!(
+            builder.value(JsonValue::String("a".to_string())),
+            Some(JsonValue::String("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_display_as_json() {
+        let mut object = IndexMap::new();
+        object.insert(
+            "a \"b\"".to_string(),
+            JsonValue::String("\n\u{1}é".to_string()),
+        );
+        object.insert(
+            "c".to_string(),
+            JsonValue::Array(vec![
+                JsonValue::Number(NumberToken::Integer(-1)),
+                JsonValue::Number(NumberToken::Float(2.0)),
+                JsonValue::Boolean(false),
+                JsonValue::Null,
+            ]),
+        );
+        assert_eq!(
+            JsonValue::Object(object).to_string(),
+            r#"{"a \"b\"":"\n\u0001é","c":[-1,2.0,false,null]}"#
+        );
+    }
+}
@@ -19,6 +19,7 @@ pub mod array;
 pub mod common;
 pub mod object;
 pub mod primitives;
+pub mod skip;
 
 pub async fn scan_token(
     c: char,
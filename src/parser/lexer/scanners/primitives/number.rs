@@ -11,7 +11,7 @@ pub fn is_first_char_of_number(c: char) -> bool {
     match c {
         '-' => true,
         '+' => true,
-        '0'..'9' => true,
+        '0'..='9' => true,
         _ => false,
     }
 }
@@ -84,22 +84,13 @@ impl NumberParsingState {
         }
     }
 
-    /**
-     * Scans the next char of a number, if the char is not part of a number then it returns
-     * it to the buffer.
-     */
-    async fn scan_char(
-        self: &mut Self,
-        c: char,
-        buffer: &mut Pin<Box<&mut Buffer>>,
-    ) -> Option<NumberParseTerminationReason> {
+    /// Scans the next char of a number.
+    fn scan_char(self: &mut Self, c: char) -> Option<NumberParseTerminationReason> {
         return match c {
-            COMMA | OBJECT_END | ARRAY_END => {
-                buffer.replace_char(c).await;
-                Some(NumberParseTerminationReason::EndOfNumber)
-            }
-            c if is_whitespace(c) => None,
-            '0'..'9' => {
+            COMMA | OBJECT_END | ARRAY_END => Some(NumberParseTerminationReason::EndOfNumber),
+            // Whitespace cannot be inside a number, so `1 2` is two values rather than `12`
+            c if is_whitespace(c) => Some(NumberParseTerminationReason::EndOfNumber),
+            '0'..='9' => {
                 if self.parts[self.current_part] == NOT_SET {
                     self.parts[self.current_part] = 0;
                 }
@@ -170,7 +161,7 @@ impl NumberParsingState {
         first_char: char,
         buffer: &mut Pin<Box<&mut Buffer>>,
     ) -> Result<NumberToken, &'static str> {
-        let parse_state = self.scan_char(first_char, buffer).await;
+        let parse_state = self.scan_char(first_char);
 
         if parse_state.is_some() {
             return match parse_state {
@@ -183,6 +174,11 @@ impl NumberParsingState {
         }
 
         loop {
+            // The char after the number is left in the buffer
+            if let Some(COMMA | OBJECT_END | ARRAY_END) = buffer.peek().await {
+                return self.as_number_token();
+            }
+
             let res = buffer.next_char().await;
 
             if res.is_err() {
@@ -190,7 +186,7 @@ impl NumberParsingState {
             }
 
             let c = res.unwrap();
-            let parse_state = self.scan_char(c, buffer).await;
+            let parse_state = self.scan_char(c);
 
             if parse_state.is_some() {
                 return match parse_state {
@@ -206,7 +202,7 @@ impl NumberParsingState {
 enum NumberParseTerminationReason {
     /// Many reasons: I/O error, EOF, bad input, etc...
     Fatal(&'static str),
-    /// This is because a ',', '}', or ']' got parsed so the number is natually over
+    /// This is because a ',', '}', ']' or whitespace got parsed so the number is natually over
     EndOfNumber,
 }
 
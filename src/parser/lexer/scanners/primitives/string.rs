@@ -3,6 +3,8 @@ use std::{char, pin::Pin};
 
 /// The maximum string length is a Gigabyte so that really long valid strings will terminate.
 const MAX_READ_LENGTH: usize = 1024 * 1024 * 1024;
+/// When streaming a string, decoded escape sequences are passed on once this many are stored.
+const MAX_PENDING_LENGTH: usize = 64 * 1024;
 
 pub fn is_first_char_of_string(c: char) -> bool {
     match c {
@@ -12,7 +14,7 @@ pub fn is_first_char_of_string(c: char) -> bool {
 }
 
 struct StringParsingState {
-    token: StringToken,
+    token: StringToken<'static>,
 }
 
 enum ScannedCharType {
@@ -143,23 +145,76 @@ impl StringParsingState {
         }
     }
 
+    /// Passes the chars that have been scanned to `f` then clears them, returns whether to keep
+    /// scanning.
+    fn flush(&mut self, f: &mut impl FnMut(&str) -> bool) -> bool {
+        if self.token.as_str().is_empty() {
+            return true;
+        }
+
+        let keep_scanning = f(self.token.as_str());
+        self.token = StringToken::new();
+        return keep_scanning;
+    }
+
+    /// Passes the decoded fragments of the string to `f` until it returns false or the end of
+    /// the string is read, returns whether the end was read.
+    async fn scan_chunks(
+        &mut self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+        mut f: impl FnMut(&str) -> bool,
+    ) -> Result<bool, &'static str> {
+        loop {
+            let borrowed = buffer
+                .borrow_plain_chars(|x| {
+                    if self.flush(&mut f) {
+                        return f(x);
+                    }
+
+                    // The chars have been read so are kept until scanning carries on
+                    self.token.add_str(x);
+                    return false;
+                })
+                .await;
+            match borrowed {
+                Some(true) => continue,
+                Some(false) => return Ok(false),
+                None => {}
+            }
+
+            let c = buffer.next_char().await?;
+            match self.scan_char(c, buffer).await {
+                CharScanResult::Err(x) => return Err(x),
+                CharScanResult::EndOfToken => {
+                    self.flush(&mut f);
+                    return Ok(true);
+                }
+                CharScanResult::Ok => {
+                    if self.token.as_str().len() >= MAX_PENDING_LENGTH && !self.flush(&mut f) {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+
     async fn scan(
         &mut self,
         buffer: &mut Pin<Box<&mut Buffer>>,
-    ) -> Result<StringToken, &'static str> {
+    ) -> Result<StringToken<'static>, &'static str> {
         for _ in 0..MAX_READ_LENGTH {
             let scan_result = match buffer.next_char().await {
                 Err(x) => Err(x),
                 Ok(c) => Ok(self.scan_char(c, buffer).await),
             };
 
-            let return_val: Option<Result<StringToken, &'static str>>;
+            let return_val: Option<Result<StringToken<'static>, &'static str>>;
             match scan_result {
                 Ok(CharScanResult::Err(x)) | Err(x) => {
                     return_val = Some(Err(x));
                 }
                 Ok(CharScanResult::EndOfToken) => {
-                    return_val = Some(Ok(self.token.clone()));
+                    return_val = Some(Ok(std::mem::replace(&mut self.token, StringToken::new())));
                 }
                 Ok(CharScanResult::Ok) => return_val = None,
             };
@@ -181,10 +236,49 @@ impl StringParsingState {
 */
 pub async fn scan_string_token(
     buffer: &mut Pin<Box<&mut Buffer>>,
-) -> Result<StringToken, &'static str> {
+) -> Result<StringToken<'static>, &'static str> {
     return StringParsingState::new().scan(buffer).await;
 }
 
+/**
+* Reads a string like `scan_string_token()`, but instead of storing the whole string it is
+* passed to `f` in decoded fragments as it is read so there is no maximum length. Runs of chars
+* without escape sequences are borrowed from the buffer. `f` returns whether to keep scanning,
+* if it returns false then the rest of the string is not read.
+*/
+pub async fn scan_string_chunks(
+    buffer: &mut Pin<Box<&mut Buffer>>,
+    f: impl FnMut(&str) -> bool,
+) -> Result<(), &'static str> {
+    StringChunkScanner::new().scan(buffer, f).await?;
+    return Ok(());
+}
+
+/// Reads a string in decoded fragments like `scan_string_chunks()`, but scanning can be carried
+/// on after `f` returns false. This lets the caller do async work with the fragments that have
+/// been passed so far before reading more of the string.
+pub struct StringChunkScanner {
+    state: StringParsingState,
+}
+
+impl StringChunkScanner {
+    pub fn new() -> Self {
+        return StringChunkScanner {
+            state: StringParsingState::new(),
+        };
+    }
+
+    /// Passes fragments to `f` until it returns false or the end of the string has been read,
+    /// returns whether the end has been read. The opening quote is expected to have been read.
+    pub async fn scan(
+        &mut self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+        f: impl FnMut(&str) -> bool,
+    ) -> Result<bool, &'static str> {
+        return self.state.scan_chunks(buffer, f).await;
+    }
+}
+
 #[cfg(test)]
 mod test_string {
     use super::*;
@@ -317,4 +411,100 @@ mod test_string {
         assert!(res.is_ok());
         assert_eq!(res.unwrap().as_string(), ">>\\<<");
     }
+
+    #[tokio::test]
+    async fn test_string_scan_chunks() {
+        let mut buffer = Buffer::new();
+        assert!(buffer.add_string("\"Hello ".to_string()).await.is_ok());
+        assert!(buffer
+            .add_string("\\u27bd\\n\\\"world\\\"".to_string())
+            .await
+            .is_ok());
+        assert!(buffer.add_string("!\", 1".to_string()).await.is_ok());
+
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        assert!(is_first_char_of_string(
+            buffer_pinned.next_char().await.unwrap()
+        ));
+
+        let mut chunks: Vec<String> = Vec::new();
+        let res = scan_string_chunks(buffer_pinned, |x| {
+            chunks.push(x.to_string());
+            true
+        })
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(chunks, vec!["Hello ", "➽\n\"", "world", "\"!"]);
+        assert_eq!(buffer_pinned.next_char().await.unwrap(), ',');
+    }
+
+    #[tokio::test]
+    async fn test_string_scan_chunks_stopped() {
+        let mut buffer = Buffer::new();
+        assert!(buffer.add_string("\"abc".to_string()).await.is_ok());
+        assert!(buffer.add_string("def\"".to_string()).await.is_ok());
+
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        assert!(is_first_char_of_string(
+            buffer_pinned.next_char().await.unwrap()
+        ));
+
+        let mut chunks: Vec<String> = Vec::new();
+        let res = scan_string_chunks(buffer_pinned, |x| {
+            chunks.push(x.to_string());
+            false
+        })
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(chunks, vec!["abc"]);
+        assert_eq!(buffer_pinned.next_char().await.unwrap(), 'd');
+    }
+
+    #[tokio::test]
+    async fn test_string_chunk_scanner_resumes() {
+        let mut buffer = Buffer::new();
+        assert!(buffer.add_string("\"ab\\tc".to_string()).await.is_ok());
+        assert!(buffer.add_string("de\"".to_string()).await.is_ok());
+        buffer.eof().await;
+
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        assert!(is_first_char_of_string(
+            buffer_pinned.next_char().await.unwrap()
+        ));
+
+        let mut scanner = StringChunkScanner::new();
+        let mut chunks: Vec<String> = Vec::new();
+        let mut scans = 0;
+        loop {
+            scans += 1;
+            let finished = scanner
+                .scan(buffer_pinned, |x| {
+                    chunks.push(x.to_string());
+                    false
+                })
+                .await
+                .unwrap();
+            if finished {
+                break;
+            }
+        }
+
+        assert_eq!(chunks.concat(), "ab\tcde");
+        assert!(scans > 1);
+        assert!(buffer_pinned.next_char().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_string_scan_chunks_invalid_escape() {
+        let mut buffer = Buffer::new();
+        assert!(buffer.add_string("\"abc\\q\"".to_string()).await.is_ok());
+
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        assert!(is_first_char_of_string(
+            buffer_pinned.next_char().await.unwrap()
+        ));
+        assert!(scan_string_chunks(buffer_pinned, |_| true).await.is_err());
+    }
 }
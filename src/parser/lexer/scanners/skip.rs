@@ -0,0 +1,224 @@
+use super::{
+    array::{is_first_char_of_array_end, is_first_char_of_array_start, ARRAY_END},
+    common::{is_first_char_of_comma, COMMA},
+    object::{is_first_char_of_object_end, is_first_char_of_object_start, OBJECT_END},
+    primitives::{
+        boolean::is_first_char_of_boolean, null::is_first_char_of_null,
+        number::is_first_char_of_number, string::is_first_char_of_string,
+    },
+};
+use crate::parser::{buffer::Buffer, lexer::tokens::whitespace_token::is_whitespace};
+use std::pin::Pin;
+
+/// Reads until the end of the string without decoding it, the opening quote has been read.
+async fn skip_string(buffer: &mut Pin<Box<&mut Buffer>>) -> Result<(), &'static str> {
+    loop {
+        match buffer.next_char().await? {
+            '\\' => {
+                buffer.next_char().await?;
+            }
+            '"' => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+/// Reads until the end of a number, boolean or null. The char after it is left in the buffer
+/// if it is the end of an object, array or an item separator.
+async fn skip_primitive(buffer: &mut Pin<Box<&mut Buffer>>) -> Result<(), &'static str> {
+    loop {
+        if let Some(COMMA | OBJECT_END | ARRAY_END) = buffer.peek().await {
+            return Ok(());
+        }
+
+        let c = buffer.next_char().await?;
+        if is_whitespace(c) {
+            return Ok(());
+        }
+
+        match c {
+            '{' | '[' | '"' | ':' => return Err("Unexpected char after a primitive"),
+            _ => {}
+        }
+    }
+}
+
+/// Reads until the end of an object or array only tracking brackets, quotes and escapes. The
+/// opening bracket has already been read, so this can skip the rest of an object or array
+/// part of the way through it.
+pub async fn skip_until_closed(
+    open_bracket: char,
+    buffer: &mut Pin<Box<&mut Buffer>>,
+) -> Result<(), &'static str> {
+    let mut open_brackets = vec![open_bracket];
+
+    while let Some(open_bracket) = open_brackets.last().copied() {
+        let c = buffer.next_char().await?;
+        if is_first_char_of_string(c) {
+            skip_string(buffer).await?;
+        } else if is_first_char_of_object_start(c) || is_first_char_of_array_start(c) {
+            open_brackets.push(c);
+        } else if is_first_char_of_object_end(c) {
+            if !is_first_char_of_object_start(open_bracket) {
+                return Err("Mismatched brackets");
+            }
+            open_brackets.pop();
+        } else if is_first_char_of_array_end(c) {
+            if !is_first_char_of_array_start(open_bracket) {
+                return Err("Mismatched brackets");
+            }
+            open_brackets.pop();
+        }
+    }
+
+    return Ok(());
+}
+
+/**
+* Reads past a whole value without scanning it into tokens, this is much faster than scanning
+* the value as nothing is decoded or allocated. Only the brackets are checked so the value may
+* not be valid JSON. The first char is expected to have been read.
+*/
+pub async fn skip_value(
+    first_char: char,
+    buffer: &mut Pin<Box<&mut Buffer>>,
+) -> Result<(), &'static str> {
+    if is_first_char_of_object_start(first_char) || is_first_char_of_array_start(first_char) {
+        return skip_until_closed(first_char, buffer).await;
+    } else if is_first_char_of_string(first_char) {
+        return skip_string(buffer).await;
+    } else if is_first_char_of_number(first_char)
+        || is_first_char_of_boolean(first_char)
+        || is_first_char_of_null(first_char)
+    {
+        return skip_primitive(buffer).await;
+    } else if is_first_char_of_comma(first_char) {
+        return Err("Expected a value");
+    } else {
+        return Err("Cannot match a valid JSON token");
+    }
+}
+
+/// Reads until the next comma or closing bracket that is not in a nested object, array or
+/// string, so that parsing can carry on from the next member after an error. `depth` is how
+/// many brackets have been opened since the member started and `in_string` is whether the error
+/// was part of the way through a string. The comma or bracket is returned, or `None` if the
+/// input ends first.
+pub async fn skip_to_delimiter(
+    mut depth: usize,
+    mut in_string: bool,
+    buffer: &mut Pin<Box<&mut Buffer>>,
+) -> Result<Option<char>, &'static str> {
+    let mut escaped = false;
+    while !buffer.is_eof().await {
+        let c = buffer.next_char().await?;
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if is_first_char_of_string(c) {
+            in_string = true;
+        } else if is_first_char_of_object_start(c) || is_first_char_of_array_start(c) {
+            depth += 1;
+        } else if is_first_char_of_object_end(c) || is_first_char_of_array_end(c) {
+            if depth == 0 {
+                return Ok(Some(c));
+            }
+            depth -= 1;
+        } else if is_first_char_of_comma(c) && depth == 0 {
+            return Ok(Some(c));
+        }
+    }
+
+    return Ok(None);
+}
+
+#[cfg(test)]
+mod test_skip {
+    use super::*;
+    use std::borrow::BorrowMut;
+
+    async fn skip(data: &str) -> (Result<(), &'static str>, Option<char>) {
+        let mut buffer = Buffer::new();
+        assert!(buffer
+            .add_data(data.chars().collect::<Vec<char>>())
+            .await
+            .is_ok());
+        buffer.eof().await;
+
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let first_char = buffer_pinned.next_char().await.unwrap();
+        let res = skip_value(first_char, buffer_pinned).await;
+        return (res, buffer_pinned.next_char().await.ok());
+    }
+
+    #[tokio::test]
+    async fn test_skip_nested() {
+        let (res, next) = skip(r#"{"a": [1, {"b": "]}\"}"}], "c": {}}, 2"#).await;
+        assert!(res.is_ok());
+        assert_eq!(next, Some(','));
+    }
+
+    #[tokio::test]
+    async fn test_skip_string() {
+        let (res, next) = skip(r#""abc\\\"{[" ]"#).await;
+        assert!(res.is_ok());
+        assert_eq!(next, Some(' '));
+    }
+
+    #[tokio::test]
+    async fn test_skip_number() {
+        let (res, next) = skip("-12.5e3}").await;
+        assert!(res.is_ok());
+        assert_eq!(next, Some('}'));
+    }
+
+    #[tokio::test]
+    async fn test_skip_literal() {
+        let (res, next) = skip("true]").await;
+        assert!(res.is_ok());
+        assert_eq!(next, Some(']'));
+    }
+
+    #[tokio::test]
+    async fn test_skip_mismatched_brackets() {
+        let (res, _) = skip(r#"{"a": [1, 2}}"#).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_skip_unterminated() {
+        let (res, _) = skip(r#"{"a": [1, 2]"#).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_skip_to_delimiter() {
+        let mut buffer = Buffer::new();
+        assert!(buffer
+            .add_data(r#"x", [1, "]"], {"a": 2}], 3"#.chars().collect::<Vec<char>>())
+            .await
+            .is_ok());
+        buffer.eof().await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+
+        assert_eq!(
+            skip_to_delimiter(0, true, buffer_pinned).await,
+            Ok(Some(','))
+        );
+        assert_eq!(
+            skip_to_delimiter(1, false, buffer_pinned).await,
+            Ok(Some(','))
+        );
+        assert_eq!(skip_to_delimiter(0, false, buffer_pinned).await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_skip_invalid_first_char() {
+        let (res, _) = skip("}").await;
+        assert!(res.is_err());
+    }
+}
@@ -11,7 +11,7 @@ pub enum JsonToken {
     Null,
     Boolean(bool),
     Number(NumberToken),
-    String(StringToken),
+    String(StringToken<'static>),
     ObjectStart,
     ObjectEnd,
     /// The colon (:) that makes up the "key": "value" of an object entry
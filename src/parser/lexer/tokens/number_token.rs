@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum NumberToken {
     Integer(i64),
     Float(f64),
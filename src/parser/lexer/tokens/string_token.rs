@@ -1,32 +1,55 @@
-use std::string::String;
+use std::borrow::Cow;
 
+/// The contents of a string, this is either owned or borrowed from the buffer when the string
+/// could be read without copying it.
 #[derive(Clone, Debug, PartialEq)]
-pub struct StringToken {
-    value: Vec<char>,
+pub struct StringToken<'a> {
+    value: Cow<'a, str>,
 }
 
-impl StringToken {
+impl<'a> StringToken<'a> {
     pub fn new() -> Self {
-        return StringToken { value: Vec::new() };
+        return StringToken {
+            value: Cow::Owned(String::new()),
+        };
     }
 
     pub fn from(s: &'static str) -> Self {
         return StringToken {
-            value: s
-                .to_string()
-                .chars()
-                .into_iter()
-                .clone()
-                .collect::<Vec<char>>(),
+            value: Cow::Borrowed(s),
+        };
+    }
+
+    pub fn borrowed(s: &'a str) -> Self {
+        return StringToken {
+            value: Cow::Borrowed(s),
         };
     }
 
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+
+    /// Gets the string, this only copies it if it is borrowed.
     pub fn as_string(self) -> String {
-        self.value.iter().cloned().collect::<String>()
+        self.value.into_owned()
+    }
+
+    pub fn as_cow(self) -> Cow<'a, str> {
+        self.value
     }
 
-    pub fn add_char(&mut self, c: char) -> &mut StringToken {
-        self.value.push(c);
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.value, Cow::Borrowed(_))
+    }
+
+    pub fn add_char(&mut self, c: char) -> &mut StringToken<'a> {
+        self.value.to_mut().push(c);
+        self
+    }
+
+    pub fn add_str(&mut self, s: &str) -> &mut StringToken<'a> {
+        self.value.to_mut().push_str(s);
         self
     }
 }
@@ -62,4 +85,14 @@ mod test_string_token {
         let as_string = string_token.as_string();
         assert_eq!(as_string, TEST_DATA);
     }
+
+    #[test]
+    fn test_borrowed_as_str() {
+        let data = "Hello world!".to_string();
+        let string_token = StringToken::borrowed(&data[6..]);
+
+        assert!(string_token.is_borrowed());
+        assert_eq!(string_token.as_str(), "world!");
+        assert_eq!(string_token.as_cow(), Cow::Borrowed("world!"));
+    }
 }
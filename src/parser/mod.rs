@@ -1,4 +1,15 @@
+pub mod base64;
 pub mod buffer;
+pub mod checkpoint;
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "bzip2"))]
+pub mod compression;
+pub mod derive;
+pub mod diagnostic;
+pub mod encoding;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod json_path;
+pub mod json_value;
 pub mod lexer;
 pub mod parser;
+pub mod schema;
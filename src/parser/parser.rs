@@ -1,62 +1,3229 @@
-use std::pin::Pin;
+use std::{any::Any, collections::HashSet, fmt, future::Future, pin::Pin, sync::Arc};
+use tokio::io::AsyncWriteExt;
 
 use super::{
-    buffer::Buffer,
-    json_path::ObjectConsumer,
-    lexer::{scanners::scan_token, tokens::JsonToken},
+    base64::{Base64Consumer, Base64Decoder},
+    buffer::{Buffer, BufferLimits, BufferWriter},
+    checkpoint::{Checkpoint, CheckpointFrame, CheckpointHandler},
+    diagnostic::Diagnostic,
+    encoding::OffsetMap,
+    json_path::{
+        ConsumerAction, EndHook, JsonPrimitive, KeyedConsumer, KeyedPrimitiveConsumer,
+        KeyedValueConsumer, ObjectConsumer, PathContext, PathSegment, PrimitiveConsumer,
+        RawConsumer, StartHook, StringChunkConsumer, StringFragment, TypeMismatch,
+        TypeMismatchHandler, TypedPrimitiveConsumer, UnknownConsumer, ValidationError,
+        ValidationHandler, ValueConsumer,
+    },
+    json_value::{JsonValue, ValueBuilder},
+    lexer::{
+        scanners::{
+            array::is_first_char_of_array_end,
+            primitives::string::{is_first_char_of_string, scan_string_chunks, StringChunkScanner},
+            scan_token,
+            skip::{skip_to_delimiter, skip_until_closed, skip_value},
+        },
+        tokens::{string_token::StringToken, whitespace_token::is_whitespace, JsonToken},
+    },
 };
 
-enum CurrentlyScanning {
-    Array,
-    Object,
-    /// The key is the value
-    KeyValuePair(String),
+/// Decoded base64 is written once this many bytes have been decoded
+const MAX_DECODED_LENGTH: usize = 64 * 1024;
+/// How much text `Parser::parse_while_writing` lets queue up before the writer waits for the
+/// parser to catch up
+const WRITE_QUEUE_LIMIT: usize = 1024 * 1024;
+/// How far the parser reads between dropping the parts of the map of input offsets that it can
+/// no longer report errors in
+const OFFSET_DISCARD_INTERVAL: usize = 4 * 1024;
+
+/// Objects and arrays have the byte offset that they start at, and the number of keys or
+/// members that have been scanned in them.
+enum CurrentlyScanning<'a> {
+    Array {
+        /// The consumer is for each member of the array
+        consumer: Option<&'a UnknownConsumer>,
+        end_hook: Option<&'a EndHook>,
+        start: usize,
+        count: usize,
+    },
+    Object {
+        consumer: Option<&'a ObjectConsumer>,
+        start: usize,
+        count: usize,
+        /// The keys that the consumer requires which have not been scanned yet
+        missing: Vec<&'a str>,
+    },
+    /// The consumers for the value of the key, the key is the last part of the path
+    KeyValuePair(ValueConsumers<'a>),
+}
+
+/// What the next token must be for the input to be valid JSON
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Expecting {
+    Value,
+    /// After an array start as arrays can be empty
+    ValueOrArrayEnd,
+    /// After an object start as objects can be empty
+    KeyOrObjectEnd,
+    Key,
+    ObjectValueIndicator,
+    CommaOrArrayEnd,
+    CommaOrObjectEnd,
+    /// The root value has been scanned, there should only be whitespace left
+    EndOfInput,
+}
+
+/// What a token does to the structure of the document, which tokens can come next is shared
+/// by everything that walks the tokens of a document.
+pub(crate) enum Step {
+    Whitespace,
+    Key(String),
+    ObjectValueIndicator,
+    /// A comma between the keys of an object
+    NextKey,
+    /// A comma between the members of an array
+    NextMember,
+    ObjectStart,
+    ArrayStart,
+    /// The end of the innermost object or array
+    ContainerEnd,
+    Primitive(JsonPrimitive<'static>),
 }
 
+impl Expecting {
+    /// Works out what the token does, failing if it cannot come next.
+    pub(crate) fn step(self, token: JsonToken) -> Result<Step, &'static str> {
+        return match (self, token) {
+            (_, JsonToken::Whitespace) => Ok(Step::Whitespace),
+            (Expecting::Key | Expecting::KeyOrObjectEnd, JsonToken::String(key)) => {
+                Ok(Step::Key(key.as_string()))
+            }
+            (Expecting::ObjectValueIndicator, JsonToken::ObjectValueIndicator) => {
+                Ok(Step::ObjectValueIndicator)
+            }
+            (Expecting::CommaOrObjectEnd, JsonToken::Comma) => Ok(Step::NextKey),
+            (Expecting::CommaOrArrayEnd, JsonToken::Comma) => Ok(Step::NextMember),
+            (Expecting::KeyOrObjectEnd | Expecting::CommaOrObjectEnd, JsonToken::ObjectEnd)
+            | (Expecting::ValueOrArrayEnd | Expecting::CommaOrArrayEnd, JsonToken::ArrayEnd) => {
+                Ok(Step::ContainerEnd)
+            }
+            (Expecting::Value | Expecting::ValueOrArrayEnd, JsonToken::ObjectStart) => {
+                Ok(Step::ObjectStart)
+            }
+            (Expecting::Value | Expecting::ValueOrArrayEnd, JsonToken::ArrayStart) => {
+                Ok(Step::ArrayStart)
+            }
+            (Expecting::Value | Expecting::ValueOrArrayEnd, token) => match as_primitive(token) {
+                Some(x) => Ok(Step::Primitive(x)),
+                None => Err("Expected a value"),
+            },
+            _ => Err("Unexpected token"),
+        };
+    }
+
+    /// Whether the char is the start of a value rather than of the next token of the object
+    /// or array that the value is in.
+    pub(crate) fn starts_value(self, c: char) -> bool {
+        return match self {
+            Expecting::Value => true,
+            Expecting::ValueOrArrayEnd => !is_first_char_of_array_end(c),
+            _ => false,
+        };
+    }
+
+    /// What comes first in an object or array that has just started, they can be empty.
+    pub(crate) fn after_start(is_object: bool) -> Self {
+        return match is_object {
+            true => Expecting::KeyOrObjectEnd,
+            false => Expecting::ValueOrArrayEnd,
+        };
+    }
+
+    /// What comes after a key and its value in an object, or after a member of an array.
+    pub(crate) fn after_member(is_object: bool) -> Self {
+        return match is_object {
+            true => Expecting::CommaOrObjectEnd,
+            false => Expecting::CommaOrArrayEnd,
+        };
+    }
+}
+
+/// A primitive consumer, which may want the key of the value
+#[derive(Clone, Copy)]
+enum PrimitiveTarget<'a> {
+    Consumer(&'a PrimitiveConsumer),
+    Keyed(&'a KeyedPrimitiveConsumer),
+    Typed(&'a TypedPrimitiveConsumer),
+}
+
+/// A value consumer, which may want the key of the value
+#[derive(Clone, Copy)]
+enum ValueTarget<'a> {
+    Consumer(&'a ValueConsumer),
+    Keyed(&'a KeyedValueConsumer),
+}
+
+/// Who the value that is being built is for
+enum BuildingFor<'a> {
+    Document,
+    Consumer(ValueTarget<'a>),
+}
+
+/// The consumers that want the value that is about to be scanned
+#[derive(Clone, Copy, Default)]
+struct ValueConsumers<'a> {
+    primitive: Option<PrimitiveTarget<'a>>,
+    object: Option<&'a ObjectConsumer>,
+    array: Option<&'a UnknownConsumer>,
+    raw: Option<&'a RawConsumer>,
+    value: Option<ValueTarget<'a>>,
+    string_chunks: Option<&'a StringChunkConsumer>,
+    base64: Option<&'a Base64Consumer>,
+    array_start_hook: Option<&'a StartHook>,
+    array_end_hook: Option<&'a EndHook>,
+}
+
+impl ValueConsumers<'_> {
+    fn is_empty(&self) -> bool {
+        return self.primitive.is_none()
+            && self.object.is_none()
+            && self.array.is_none()
+            && self.raw.is_none()
+            && self.value.is_none()
+            && self.string_chunks.is_none()
+            && self.base64.is_none()
+            && self.array_start_hook.is_none()
+            && self.array_end_hook.is_none();
+    }
+
+    /// Whether there are other consumers for the value as well as an object consumer, only one
+    /// of them is called for a value so the key counts once.
+    fn shares_object(&self) -> bool {
+        let others = ValueConsumers {
+            object: None,
+            array_start_hook: None,
+            array_end_hook: None,
+            ..*self
+        };
+        return self.object.is_some() && !others.is_empty();
+    }
+}
+
+struct ParserState<'a> {
+    json_path: &'a ObjectConsumer,
+    stack: Vec<CurrentlyScanning<'a>>,
+    /// The keys and array indices to the value that is being scanned
+    path: Vec<PathSegment>,
+    /// The byte offset of the first char of the value that is being scanned
+    offset: usize,
+    /// The first char of the value or token that is being scanned
+    first_char: Option<char>,
+    expecting: Expecting,
+    /// The depth of the stack that the value the buffer is capturing for a raw consumer is at
+    capture: Option<usize>,
+    /// Builds the value for a value consumer, or the whole document if there is no consumer
+    building: Option<(ValueBuilder, BuildingFor<'a>)>,
+    /// The whole document once it has been built
+    document: Option<JsonValue>,
+    skip_unregistered: bool,
+    /// What the consumers that were called for the last value want to happen next
+    action: ConsumerAction,
+    stop_when_complete: bool,
+    /// The paths that consumers have been called for so far, a path with more than one consumer
+    /// is only counted once. Consumers inside of arrays are not included as they are called
+    /// many times.
+    called: HashSet<Vec<PathSegment>>,
+    required_calls: usize,
+    /// Where the error happened when it was not at the current offset of the buffer
+    error_offset: Option<usize>,
+    /// Called when a value has the wrong type for its typed consumer, instead of failing
+    type_mismatch_handler: Option<TypeMismatchHandler>,
+    /// Called when an object does not have the shape its consumer describes, instead of failing
+    validation_handler: Option<ValidationHandler>,
+    /// How many bytes there are between checkpoints, and what is called with them
+    checkpoints: Option<(usize, CheckpointHandler)>,
+    /// The offset after which the next checkpoint is taken
+    next_checkpoint: usize,
+    /// The errors so far when recovering from errors rather than failing on the first one
+    diagnostics: Option<Vec<ParseError>>,
+    /// Called when the root of the document is an array and it starts and ends
+    root_array_hooks: (Option<&'a StartHook>, Option<&'a EndHook>),
+    /// Passed to every consumer in its context
+    consumer_state: Option<&'a (dyn Any + Send + Sync)>,
+    /// Where the text came from when it was decoded, errors are reported at offsets in the input
+    input_offsets: Option<&'a OffsetMap>,
+    /// The offset after which the runs of the offset map before the current token are dropped
+    next_offset_discard: usize,
+}
+
+/// Why parsing failed and where in the input it failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: &'static str,
+    /// The byte offset from the start of the input
+    pub offset: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{} at byte {}", self.message, self.offset);
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 pub struct Parser {
     json_path: ObjectConsumer,
+    skip_unregistered: bool,
+    stop_when_complete: bool,
+    type_mismatch_handler: Option<TypeMismatchHandler>,
+    validation_handler: Option<ValidationHandler>,
+    checkpoints: Option<(usize, CheckpointHandler)>,
+    root_array_hooks: (Option<StartHook>, Option<EndHook>),
+    input_offsets: Option<OffsetMap>,
+    consumer_state: Option<Arc<dyn Any + Send + Sync>>,
 }
 
-impl Parser {
-    pub fn new(json_path: ObjectConsumer) -> Self {
-        return Self { json_path };
+/// Reads the next char that is not whitespace, `None` is returned when the input has all been
+/// read.
+pub(crate) async fn next_char_or_eof(
+    buffer: &mut Pin<Box<&mut Buffer>>,
+) -> Result<Option<char>, &'static str> {
+    // Peeking waits for the next char or the end of the input, so the input ending while it
+    // waits is not an error
+    while buffer.peek().await.is_some() {
+        let c = buffer.next_char().await?;
+        if !is_whitespace(c) {
+            return Ok(Some(c));
+        }
+    }
+    return Ok(None);
+}
+
+/// Describes where an offset is as its line if it is still in the buffer's history.
+async fn describe_offset(offset: usize, buffer: &mut Pin<Box<&mut Buffer>>) -> String {
+    return match buffer.snippet(offset).await {
+        Some(x) => format!("at line {}", x.line),
+        None => format!("at byte {}", offset),
+    };
+}
+
+/// Finds the consumers for the value of .key in an object. Consumers registered for exactly
+/// .key are used before wildcard consumers, and raw and value consumers are used before the
+/// others.
+fn consumers_for_key<'a>(consumer: &'a ObjectConsumer, key: &str) -> ValueConsumers<'a> {
+    if let Some(raw) = consumer.raw_consumer(key) {
+        return ValueConsumers {
+            raw: Some(raw),
+            ..Default::default()
+        };
+    } else if let Some(value) = consumer.value_consumer(key) {
+        return ValueConsumers {
+            value: Some(ValueTarget::Consumer(value)),
+            ..Default::default()
+        };
+    }
+
+    let consumers = ValueConsumers {
+        primitive: consumer
+            .primitive_consumer(key)
+            .map(PrimitiveTarget::Consumer)
+            .or_else(|| consumer.typed_consumer(key).map(PrimitiveTarget::Typed)),
+        object: consumer.object_consumer(key),
+        array: consumer.array_consumer(key),
+        string_chunks: consumer.string_chunk_consumer(key),
+        base64: consumer.base64_consumer(key),
+        array_start_hook: consumer.array_start_hook(key),
+        array_end_hook: consumer.array_end_hook(key),
+        ..Default::default()
+    };
+    if !consumers.is_empty() {
+        return consumers;
+    }
+
+    return match consumer.keyed_consumer(key) {
+        Some(KeyedConsumer::PrimitiveConsumer(x)) => ValueConsumers {
+            primitive: Some(PrimitiveTarget::Keyed(x)),
+            ..Default::default()
+        },
+        Some(KeyedConsumer::ValueConsumer(x)) => ValueConsumers {
+            value: Some(ValueTarget::Keyed(x)),
+            ..Default::default()
+        },
+        Some(KeyedConsumer::ObjectConsumer(x)) => ValueConsumers {
+            object: Some(x),
+            ..Default::default()
+        },
+        None => consumers,
+    };
+}
+
+/// Finds the consumers for the member of an array at the index.
+fn consumers_for_member(consumer: &UnknownConsumer, index: usize) -> ValueConsumers<'_> {
+    return match consumer {
+        UnknownConsumer::PrimitiveConsumer(x) => ValueConsumers {
+            primitive: Some(PrimitiveTarget::Consumer(x)),
+            ..Default::default()
+        },
+        UnknownConsumer::ObjectConsumer(x) => ValueConsumers {
+            object: Some(x),
+            ..Default::default()
+        },
+        UnknownConsumer::ValueConsumer(x) => ValueConsumers {
+            value: Some(ValueTarget::Consumer(x)),
+            ..Default::default()
+        },
+        UnknownConsumer::ArrayConsumer(x, start, end) => ValueConsumers {
+            array: Some(x),
+            array_start_hook: start.as_ref(),
+            array_end_hook: end.as_ref(),
+            ..Default::default()
+        },
+        UnknownConsumer::ByType(x) => ValueConsumers {
+            primitive: x.primitive_consumer().map(PrimitiveTarget::Consumer),
+            object: x.object_consumer(),
+            array: x.array_consumer(),
+            ..Default::default()
+        },
+        UnknownConsumer::Tuple(members, rest) => match members.get(index).or(rest.as_deref()) {
+            Some(x) => consumers_for_member(x, index),
+            None => ValueConsumers::default(),
+        },
+    };
+}
+
+pub(crate) fn as_primitive(token: JsonToken) -> Option<JsonPrimitive<'static>> {
+    return match token {
+        JsonToken::Null => Some(JsonPrimitive::Null),
+        JsonToken::Boolean(x) => Some(JsonPrimitive::Boolean(x)),
+        JsonToken::Number(x) => Some(JsonPrimitive::Number(x)),
+        JsonToken::String(x) => Some(JsonPrimitive::String(x)),
+        _ => None,
+    };
+}
+
+impl<'a> ParserState<'a> {
+    fn new(json_path: &'a ObjectConsumer) -> Self {
+        return ParserState {
+            json_path,
+            stack: Vec::new(),
+            path: Vec::new(),
+            offset: 0,
+            first_char: None,
+            expecting: Expecting::Value,
+            capture: None,
+            building: None,
+            document: None,
+            skip_unregistered: false,
+            action: ConsumerAction::Continue,
+            stop_when_complete: false,
+            called: HashSet::new(),
+            required_calls: json_path.required_calls(),
+            error_offset: None,
+            type_mismatch_handler: None,
+            validation_handler: None,
+            checkpoints: None,
+            next_checkpoint: 0,
+            diagnostics: None,
+            root_array_hooks: (None, None),
+            consumer_state: None,
+            input_offsets: None,
+            next_offset_discard: 0,
+        };
+    }
+
+    /// The offset in the input of an offset in the text that is parsed
+    fn input_offset(&self, offset: usize) -> usize {
+        return match self.input_offsets {
+            Some(x) => x.input_offset(offset),
+            None => offset,
+        };
+    }
+
+    /// Records that the consumers for the current path have been called and what they want to
+    /// do next.
+    fn consumed(&mut self, action: ConsumerAction) {
+        self.action = self.action.max(action);
+
+        let in_array = self.stack.iter().any(|x| {
+            matches!(
+                x,
+                CurrentlyScanning::Array {
+                    consumer: Some(_),
+                    ..
+                }
+            )
+        });
+        if !self.stop_when_complete || in_array {
+            return;
+        }
+
+        // Anything called inside of a key that shares its object consumer counts as the key
+        let mut keys = 0;
+        for x in &self.stack {
+            if let CurrentlyScanning::KeyValuePair(consumers) = x {
+                keys += 1;
+                if consumers.shares_object() {
+                    break;
+                }
+            }
+        }
+        self.called
+            .insert(self.path.iter().take(keys).cloned().collect());
+        if self.called.len() >= self.required_calls {
+            self.action = ConsumerAction::Stop;
+        }
+    }
+
+    /// Adds a scanned value to the value that is being built, passing it on once it is whole.
+    fn build(&mut self, f: impl FnOnce(&mut ValueBuilder) -> Option<JsonValue>) {
+        let value = match self.building.as_mut() {
+            Some((builder, _)) => f(builder),
+            None => return,
+        };
+
+        if let Some(value) = value {
+            match self.building.take() {
+                Some((_, BuildingFor::Consumer(consumer))) => self.call_value(consumer, value),
+                _ => self.document = Some(value),
+            }
+        }
+    }
+
+    /// Starts building the value that is about to be scanned if it is for a value consumer.
+    fn start_building(&mut self, consumers: ValueConsumers<'a>) {
+        if let Some(value) = consumers.value {
+            self.building = Some((ValueBuilder::new(), BuildingFor::Consumer(value)));
+        }
+    }
+
+    /// Finds the consumers for the value that is about to be scanned from the registered
+    /// consumers of the object or array that it is in.
+    fn value_consumers(&self) -> ValueConsumers<'a> {
+        if self.capture.is_some() || self.building.is_some() {
+            return ValueConsumers::default();
+        }
+
+        return match self.stack.last() {
+            None => ValueConsumers {
+                object: Some(self.json_path),
+                array_start_hook: self.root_array_hooks.0,
+                array_end_hook: self.root_array_hooks.1,
+                ..Default::default()
+            },
+            Some(CurrentlyScanning::Array {
+                consumer: Some(consumer),
+                ..
+            }) => match self.path.last() {
+                Some(PathSegment::Index(i)) => consumers_for_member(consumer, *i),
+                _ => ValueConsumers::default(),
+            },
+            Some(CurrentlyScanning::KeyValuePair(consumers)) => *consumers,
+            _ => ValueConsumers::default(),
+        };
+    }
+
+    /// Where the value that is being scanned is, for passing to its consumers
+    fn context(&self) -> PathContext<'_> {
+        return PathContext::new(&self.path, self.offset).with_state(self.consumer_state);
+    }
+
+    /// Calls a primitive consumer and records what it wants to do next. Fails if the consumer
+    /// wants a different type of value and there is no handler for that.
+    fn call_primitive(
+        &mut self,
+        consumer: PrimitiveTarget<'a>,
+        primitive: JsonPrimitive,
+    ) -> Result<(), &'static str> {
+        let context = self.context();
+        match consumer {
+            PrimitiveTarget::Consumer(f) => {
+                let action = f(primitive, &context);
+                self.consumed(action);
+            }
+            PrimitiveTarget::Keyed(f) => {
+                let action = f(context.key().unwrap_or_default(), primitive, &context);
+                self.consumed(action);
+            }
+            PrimitiveTarget::Typed(f) => match f.call(primitive, &context) {
+                Ok(action) => self.consumed(action),
+                Err(mismatch) => return self.type_mismatch(mismatch),
+            },
+        }
+
+        return Ok(());
+    }
+
+    /// Passes a value with the wrong type for its typed consumer to the handler, or fails with
+    /// an error at the start of the value if there is no handler.
+    fn type_mismatch(&mut self, mismatch: TypeMismatch) -> Result<(), &'static str> {
+        return match self.type_mismatch_handler {
+            Some(handler) => {
+                let action = handler(mismatch, &self.context());
+                self.consumed(action);
+                Ok(())
+            }
+            None => self.fail(mismatch.expected, self.offset),
+        };
+    }
+
+    /// Passes an object that does not have the shape its consumer describes to the handler, or
+    /// fails with an error at the offset if there is no handler.
+    fn invalid(&mut self, error: ValidationError, offset: usize) -> Result<(), &'static str> {
+        return match self.validation_handler {
+            Some(handler) => {
+                let action = handler(error, &self.context());
+                self.action = self.action.max(action);
+                Ok(())
+            }
+            None => self.fail(error.message(), offset),
+        };
+    }
+
+    /// Fails with an error at the offset, or records it and carries on when recovering from
+    /// errors as the input is still valid JSON.
+    fn fail(&mut self, message: &'static str, offset: usize) -> Result<(), &'static str> {
+        // A recorded error is mapped to the input now, as the start of the map is dropped while
+        // parsing
+        let input_offset = self.input_offset(offset);
+        return match self.diagnostics.as_mut() {
+            Some(diagnostics) => {
+                diagnostics.push(ParseError {
+                    message,
+                    offset: input_offset,
+                });
+                Ok(())
+            }
+            None => {
+                self.error_offset = Some(offset);
+                Err(message)
+            }
+        };
+    }
+
+    /// Calls a start or end hook, these are not counted as consumers when working out if every
+    /// consumer has been called.
+    fn call_hook(&mut self, hook: impl FnOnce(&PathContext) -> ConsumerAction) {
+        let action = hook(&self.context());
+        self.action = self.action.max(action);
+    }
+
+    /// Calls a value consumer and records what it wants to do next
+    fn call_value(&mut self, consumer: ValueTarget<'a>, value: JsonValue) {
+        let context = self.context();
+        match consumer {
+            ValueTarget::Consumer(f) => {
+                let action = f(value, &context);
+                self.consumed(action);
+            }
+            ValueTarget::Keyed(f) => {
+                let action = f(context.key().unwrap_or_default(), value, &context);
+                self.consumed(action);
+            }
+        }
+    }
+
+    /// Called once a whole value has been scanned, including the end of an object or array.
+    async fn end_value(&mut self, buffer: &mut Pin<Box<&mut Buffer>>) {
+        if let Some(depth) = self.capture {
+            if depth == self.stack.len() {
+                let action = buffer.end_capture().await;
+                self.capture = None;
+                self.consumed(action);
+            }
+        }
+
+        if let Some(CurrentlyScanning::KeyValuePair(_)) = self.stack.last() {
+            self.stack.pop();
+            self.path.pop();
+        }
+
+        self.expecting = match self.stack.last_mut() {
+            Some(CurrentlyScanning::Array { count, .. }) => {
+                *count += 1;
+                Expecting::after_member(false)
+            }
+            Some(CurrentlyScanning::Object { count, .. }) => {
+                *count += 1;
+                Expecting::after_member(true)
+            }
+            _ => Expecting::EndOfInput,
+        };
+    }
+
+    /// Called once the end of the object or array at the top of the stack has been read.
+    async fn end_container(
+        &mut self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<(), &'static str> {
+        match self.stack.pop() {
+            Some(CurrentlyScanning::Array {
+                consumer,
+                end_hook,
+                start,
+                count,
+            }) => {
+                self.path.pop();
+                self.offset = start;
+                if consumer.is_some() {
+                    self.consumed(ConsumerAction::Continue);
+                }
+                if let Some(hook) = end_hook {
+                    self.call_hook(|context| hook(count, context));
+                }
+            }
+            Some(CurrentlyScanning::Object {
+                consumer,
+                start,
+                count,
+                missing,
+            }) => {
+                let end = self.offset;
+                self.offset = start;
+                if !missing.is_empty() {
+                    let keys = missing.into_iter().map(String::from).collect();
+                    self.invalid(ValidationError::MissingKeys(keys), end)?;
+                }
+                if let Some(hook) = consumer.and_then(|x| x.end_hook()) {
+                    self.call_hook(|context| hook(count, context));
+                }
+            }
+            _ => {}
+        }
+
+        self.build(|x| x.end());
+        self.end_value(buffer).await;
+        return Ok(());
     }
 
-    pub async fn parse(self, buffer: &mut Pin<Box<&mut Buffer>>) -> Result<(), &'static str> {
-        let mut state_stack: Vec<CurrentlyScanning> = Vec::new();
+    /// Skips the rest of the object or array that the last value was in.
+    async fn skip_subtree(
+        &mut self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<(), &'static str> {
+        let open_bracket = match self.stack.last() {
+            Some(CurrentlyScanning::Array { .. }) => '[',
+            Some(CurrentlyScanning::Object { .. }) => '{',
+            _ => return Ok(()),
+        };
 
+        // The skipped keys are not seen, so it is not known if any required keys are missing
+        if let Some(CurrentlyScanning::Object { missing, .. }) = self.stack.last_mut() {
+            missing.clear();
+        }
+
+        skip_until_closed(open_bracket, buffer).await?;
+        return self.end_container(buffer).await;
+    }
+
+    /// Whether the value that starts with the char can be skipped as no consumer wants it
+    fn can_skip_value(&self, first_char: char) -> bool {
+        return self.skip_unregistered
+            && self.expecting.starts_value(first_char)
+            && self.value_consumers().is_empty()
+            && self.capture.is_none()
+            && self.building.is_none();
+    }
+
+    /// Whether the char is the start of a string in the place of a value
+    fn is_string_value(&self, first_char: char) -> bool {
+        return is_first_char_of_string(first_char)
+            && matches!(
+                self.expecting,
+                Expecting::Value | Expecting::ValueOrArrayEnd
+            );
+    }
+
+    /// Passes a string value to its consumer borrowed from the buffer, if it can be borrowed.
+    /// Returns whether the string was consumed.
+    async fn consume_borrowed_string(
+        &mut self,
+        first_char: char,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<bool, &'static str> {
+        if !self.is_string_value(first_char) {
+            return Ok(false);
+        }
+
+        let consumer = match self.value_consumers().primitive {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+
+        let borrowed = buffer
+            .borrow_plain_string(|x| {
+                self.call_primitive(consumer, JsonPrimitive::String(StringToken::borrowed(x)))
+            })
+            .await;
+        match borrowed {
+            Some(res) => res?,
+            None => return Ok(false),
+        }
+
+        self.end_value(buffer).await;
+        return Ok(true);
+    }
+
+    /// Passes a string value to its consumer in parts as it is scanned. Returns whether the
+    /// string was consumed.
+    async fn consume_string_chunks(
+        &mut self,
+        first_char: char,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<bool, &'static str> {
+        if !self.is_string_value(first_char) {
+            return Ok(false);
+        }
+
+        let consumer = match self.value_consumers().string_chunks {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+
+        let context = self.context();
+        let mut action = ConsumerAction::Continue;
+        scan_string_chunks(buffer, |x| {
+            if action == ConsumerAction::Continue {
+                action = consumer(StringFragment::Chunk(x), &context);
+            }
+            return action != ConsumerAction::Stop;
+        })
+        .await?;
+
+        if action == ConsumerAction::Continue {
+            action = consumer(StringFragment::End, &context);
+        }
+        self.consumed(action);
+        self.end_value(buffer).await;
+        return Ok(true);
+    }
+
+    /// Decodes a base64 string value into the consumer's writer as it is scanned. Returns
+    /// whether the string was consumed.
+    async fn consume_base64(
+        &mut self,
+        first_char: char,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<bool, &'static str> {
+        if !self.is_string_value(first_char) {
+            return Ok(false);
+        }
+
+        let consumer = match self.value_consumers().base64 {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+
+        let start = buffer.offset().await;
+        let mut decoder = Base64Decoder::new(consumer.alphabet);
+        let mut scanner = StringChunkScanner::new();
+        let mut decoded = Vec::new();
+        let mut writer = consumer.writer.lock().await;
         loop {
-            if buffer.is_eof().await {
+            let mut res = Ok(());
+            let finished = scanner
+                .scan(buffer, |x| {
+                    res = decoder.decode(x, &mut decoded);
+                    return res.is_ok() && decoded.len() < MAX_DECODED_LENGTH;
+                })
+                .await?;
+            if finished && res.is_ok() {
+                res = decoder.finish(&mut decoded);
+            }
+
+            if let Err(x) = res {
+                // Escape sequences are not counted, but are not valid base64 anyway
+                self.error_offset = Some(start + decoder.position());
+                return Err(x);
+            }
+
+            if writer.write_all(&decoded).await.is_err() {
+                return Err("Cannot write the decoded base64");
+            }
+            decoded.clear();
+
+            if finished {
                 break;
             }
+        }
 
-            let res: Result<JsonToken, &'static str> = match buffer.next_char().await {
-                Ok(c) => match scan_token(c, buffer).await {
-                    Ok(token) => Ok(token),
-                    Err(e) => Err(e),
-                },
-                Err(e) => Err(e),
+        if writer.flush().await.is_err() {
+            return Err("Cannot write the decoded base64");
+        }
+        drop(writer);
+
+        self.consumed(ConsumerAction::Continue);
+        self.end_value(buffer).await;
+        return Ok(true);
+    }
+
+    /// Reads the value or token that starts with the char. Values that are passed straight to
+    /// their consumers or skipped are handled here, otherwise the token is returned.
+    async fn read_token(
+        &mut self,
+        c: char,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<Option<JsonToken>, &'static str> {
+        if self.consume_borrowed_string(c, buffer).await?
+            || self.consume_string_chunks(c, buffer).await?
+            || self.consume_base64(c, buffer).await?
+        {
+            // The string has already been passed to its consumer
+            return Ok(None);
+        } else if self.can_skip_value(c) {
+            skip_value(c, buffer).await?;
+            self.end_value(buffer).await;
+            return Ok(None);
+        }
+
+        return Ok(Some(scan_token(c, buffer).await?));
+    }
+
+    /// Records an error and skips to the next member of the object or array that it happened
+    /// in, so that the rest of the input can still be parsed. `in_token` is whether the error
+    /// happened part of the way through reading the token that starts with the char. Fails with
+    /// the error if not recovering from errors.
+    async fn recover(
+        &mut self,
+        message: &'static str,
+        c: char,
+        in_token: bool,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<(), &'static str> {
+        if self.diagnostics.is_none() {
+            return Err(message);
+        }
+        let offset = match self.error_offset.take() {
+            Some(x) => x,
+            None => buffer.offset().await,
+        };
+        self.fail(message, offset)?;
+
+        // The value that the error is in is not valid, so it is not passed to its consumer
+        if self.capture.take().is_some() {
+            buffer.cancel_capture().await;
+        }
+        self.building = None;
+        if let Some(CurrentlyScanning::KeyValuePair(_)) = self.stack.last() {
+            self.stack.pop();
+            self.path.pop();
+        }
+
+        if self.stack.is_empty() {
+            // There is nothing to carry on with after the root value
+            while buffer.peek().await.is_some() {
+                buffer.next_char().await?;
+            }
+            self.expecting = Expecting::EndOfInput;
+            return Ok(());
+        }
+
+        let delimiter = match c {
+            ',' | ']' | '}' => Some(c),
+            _ if in_token && is_first_char_of_string(c) => {
+                skip_to_delimiter(0, true, buffer).await?
+            }
+            _ if in_token => {
+                // The char that the scanner failed on may be the comma or bracket
+                buffer.unread_last_char().await;
+                skip_to_delimiter(0, false, buffer).await?
+            }
+            '[' | '{' => skip_to_delimiter(1, false, buffer).await?,
+            _ => skip_to_delimiter(0, false, buffer).await?,
+        };
+        let delimiter = match delimiter {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        // The skipped keys are not seen, so it is not known if any required keys are missing
+        if let Some(CurrentlyScanning::Object { missing, .. }) = self.stack.last_mut() {
+            missing.clear();
+        }
+
+        self.offset = buffer.offset().await - delimiter.len_utf8();
+        match (self.stack.last(), delimiter) {
+            (Some(CurrentlyScanning::Array { .. }), ',') => {
+                if let Some(PathSegment::Index(i)) = self.path.last_mut() {
+                    *i += 1;
+                }
+                self.expecting = Expecting::Value;
+            }
+            (_, ',') => self.expecting = Expecting::Key,
+            // The object or array is ended even if the bracket does not match, which is also
+            // an error that has already been reported
+            _ => self.end_container(buffer).await?,
+        }
+
+        return Ok(());
+    }
+
+    async fn run(&mut self, buffer: &mut Pin<Box<&mut Buffer>>) -> Result<(), &'static str> {
+        while let Some(c) = next_char_or_eof(buffer).await? {
+            self.offset = buffer.offset().await - c.len_utf8();
+            self.first_char = Some(c);
+            self.discard_offsets();
+            let res = match self.read_token(c, buffer).await {
+                Ok(Some(token)) => self.scan(token, buffer).await.map_err(|x| (x, false)),
+                Ok(None) => Ok(()),
+                Err(x) => Err((x, true)),
             };
+            if let Err((message, in_token)) = res {
+                self.recover(message, c, in_token, buffer).await?;
+            }
+
+            match std::mem::take(&mut self.action) {
+                ConsumerAction::Continue => {}
+                ConsumerAction::SkipSubtree => self.skip_subtree(buffer).await?,
+                ConsumerAction::Stop => {
+                    buffer.close().await;
+                    return Ok(());
+                }
+            }
+
+            self.take_checkpoint(buffer).await;
+        }
+
+        if self.expecting != Expecting::EndOfInput {
+            let offset = buffer.offset().await;
+            return self.fail("Unexpected end of input", offset);
+        }
+
+        return Ok(());
+    }
+
+    /// Passes a checkpoint to the handler if a value has just ended and enough of the input has
+    /// been read since the last one. Checkpoints are not taken inside of a value that is being
+    /// captured or built, as that would not be saved in the checkpoint.
+    /// Drops the runs of the offset map before the token that is being read, no error can be
+    /// reported before the start of it.
+    fn discard_offsets(&mut self) {
+        if let Some(offsets) = self.input_offsets {
+            if self.offset >= self.next_offset_discard {
+                offsets.discard_before(self.offset);
+                self.next_offset_discard = self.offset + OFFSET_DISCARD_INTERVAL;
+            }
+        }
+    }
+
+    async fn take_checkpoint(&mut self, buffer: &mut Pin<Box<&mut Buffer>>) {
+        let (interval, handler) = match self.checkpoints {
+            Some(x) => x,
+            None => return,
+        };
+
+        let is_value_boundary = matches!(
+            self.expecting,
+            Expecting::CommaOrObjectEnd | Expecting::CommaOrArrayEnd
+        ) && self.capture.is_none()
+            && self.building.is_none();
+        if !is_value_boundary {
+            return;
+        }
+        let offset = buffer.offset().await;
+        if offset < self.next_checkpoint {
+            return;
+        }
+
+        let frames = self
+            .stack
+            .iter()
+            .map(|x| match x {
+                CurrentlyScanning::Array { start, count, .. } => CheckpointFrame::Array {
+                    start: *start,
+                    count: *count,
+                },
+                CurrentlyScanning::Object {
+                    start,
+                    count,
+                    missing,
+                    ..
+                } => CheckpointFrame::Object {
+                    start: *start,
+                    count: *count,
+                    missing: missing.iter().map(|x| x.to_string()).collect(),
+                },
+                CurrentlyScanning::KeyValuePair(_) => CheckpointFrame::KeyValuePair,
+            })
+            .collect();
+        handler(&Checkpoint::new(offset, self.path.clone(), frames));
+        self.next_checkpoint = offset + interval;
+    }
+
+    /// Rebuilds the stack from a checkpoint, finding the consumers of each object and array in
+    /// it from the path in the same way as when they were scanned.
+    fn restore(&mut self, checkpoint: &Checkpoint) -> Result<(), &'static str> {
+        let mut path = checkpoint.path().iter();
+        for frame in checkpoint.frames() {
+            let consumers = self.value_consumers();
+            match frame {
+                CheckpointFrame::Array { start, count } => {
+                    let index = match path.next() {
+                        Some(PathSegment::Index(x)) => *x,
+                        _ => return Err("Invalid checkpoint"),
+                    };
+                    self.stack.push(CurrentlyScanning::Array {
+                        consumer: consumers.array,
+                        end_hook: consumers.array_end_hook,
+                        start: *start,
+                        count: *count,
+                    });
+                    self.path.push(PathSegment::Index(index));
+                }
+                CheckpointFrame::Object {
+                    start,
+                    count,
+                    missing,
+                } => {
+                    let missing = match consumers.object {
+                        Some(x) => x
+                            .required_keys()
+                            .iter()
+                            .map(String::as_str)
+                            .filter(|x| missing.iter().any(|key| key == x))
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    self.stack.push(CurrentlyScanning::Object {
+                        consumer: consumers.object,
+                        start: *start,
+                        count: *count,
+                        missing,
+                    });
+                }
+                CheckpointFrame::KeyValuePair => {
+                    let key = match path.next() {
+                        Some(PathSegment::Key(x)) => x,
+                        _ => return Err("Invalid checkpoint"),
+                    };
+                    let consumers = match self.stack.last() {
+                        Some(CurrentlyScanning::Object {
+                            consumer: Some(consumer),
+                            ..
+                        }) => consumers_for_key(consumer, key),
+                        _ => ValueConsumers::default(),
+                    };
+                    self.stack.push(CurrentlyScanning::KeyValuePair(consumers));
+                    self.path.push(PathSegment::Key(key.clone()));
+                }
+            }
+        }
+
+        if path.next().is_some() {
+            return Err("Invalid checkpoint");
+        }
+
+        self.offset = checkpoint.offset();
+        self.next_checkpoint = checkpoint.offset();
+        self.expecting = match self.stack.last() {
+            Some(CurrentlyScanning::Array { .. }) => Expecting::after_member(false),
+            Some(CurrentlyScanning::Object { .. }) => Expecting::after_member(true),
+            _ => return Err("Invalid checkpoint"),
+        };
+        return Ok(());
+    }
 
-            if res.is_err() {
-                return Err(res.err().unwrap());
-            }
-
-            let parse_res: Result<(), &'static str> = match res.unwrap() {
-                JsonToken::Whitespace => Ok(()),
-                JsonToken::Null => todo!("handle me"),
-                JsonToken::Boolean(boolean) => todo!("handle me"),
-                JsonToken::Number(number) => todo!("handle me"),
-                JsonToken::String(string) => todo!("handle me"),
-                JsonToken::ObjectValueIndicator => todo!("handle me"),
-                JsonToken::Comma => todo!("handle me"),
-                JsonToken::ArrayStart => todo!("handle me"),
-                JsonToken::ObjectStart => todo!("handle me"),
-                JsonToken::ArrayEnd => todo!("handle me"),
-                JsonToken::ObjectEnd => todo!("handle me"),
+    /// Runs the parser, adding where the error happened to any error.
+    async fn run_with_offset(
+        &mut self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<(), ParseError> {
+        if let Err(message) = self.run(buffer).await {
+            let offset = match self.error_offset {
+                Some(x) => x,
+                None => buffer.offset().await,
             };
+            return Err(ParseError { message, offset });
         }
 
         return Ok(());
     }
+
+    /// Runs the parser, adding the line that any error is on and a hint about it to the error.
+    async fn run_with_diagnostic(
+        &mut self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<(), Diagnostic> {
+        let error = match self.run_with_offset(buffer).await {
+            Ok(()) => return Ok(()),
+            Err(x) => x,
+        };
+
+        // Errors found while reading are just past the char that they are about
+        let at = match self.error_offset {
+            Some(x) => x,
+            None => buffer
+                .previous_char_offset(error.offset)
+                .await
+                .unwrap_or(error.offset),
+        };
+        return Err(Diagnostic {
+            message: error.message,
+            offset: error.offset,
+            path: self.path.clone(),
+            snippet: buffer.snippet(at).await,
+            hint: self.hint(error, buffer).await,
+        });
+    }
+
+    /// Guesses what is wrong with the input from where the parser was when the error happened.
+    async fn hint(&self, error: ParseError, buffer: &mut Pin<Box<&mut Buffer>>) -> Option<String> {
+        let container = self.stack.iter().rev().find_map(|x| match x {
+            CurrentlyScanning::Array { start, .. } => Some(("array", *start)),
+            CurrentlyScanning::Object { start, .. } => Some(("object", *start)),
+            CurrentlyScanning::KeyValuePair(_) => None,
+        });
+        let in_array = matches!(self.stack.last(), Some(CurrentlyScanning::Array { .. }));
+        let at_end = matches!(error.message, "Unexpected end of input" | "EOF reached");
+        let c = match self.first_char {
+            Some(x) => x,
+            None if at_end => return Some("the input is empty".to_string()),
+            None => return None,
+        };
+        let is_string = is_first_char_of_string(c)
+            && !matches!(
+                self.expecting,
+                Expecting::CommaOrObjectEnd | Expecting::CommaOrArrayEnd | Expecting::EndOfInput
+            );
+
+        let hint = match (self.expecting, c) {
+            _ if is_string && at_end => {
+                format!(
+                    "unterminated string started {}",
+                    describe_offset(self.offset, buffer).await
+                )
+            }
+            _ if at_end => match container {
+                Some((kind, start)) => {
+                    format!(
+                        "unclosed {} started {}",
+                        kind,
+                        describe_offset(start, buffer).await
+                    )
+                }
+                None => return None,
+            },
+            (_, '\'') => "strings have to be in double quotes".to_string(),
+            (Expecting::Key, '}') => "trailing comma".to_string(),
+            (Expecting::Value, ']') if in_array => "trailing comma".to_string(),
+            (Expecting::Key | Expecting::KeyOrObjectEnd, _) if !is_first_char_of_string(c) => {
+                "keys have to be strings in double quotes".to_string()
+            }
+            (Expecting::ObjectValueIndicator, _) => "missing colon after the key".to_string(),
+            (Expecting::CommaOrObjectEnd, ']') | (Expecting::CommaOrArrayEnd, '}') => {
+                match container {
+                    Some((kind, start)) => format!(
+                        "the bracket does not match the {} started {}",
+                        kind,
+                        describe_offset(start, buffer).await
+                    ),
+                    None => return None,
+                }
+            }
+            (Expecting::CommaOrObjectEnd | Expecting::CommaOrArrayEnd, _) => {
+                "missing comma".to_string()
+            }
+            (Expecting::EndOfInput, _) => "the root value has already ended".to_string(),
+            _ => return None,
+        };
+        return Some(hint);
+    }
+
+    async fn scan(
+        &mut self,
+        token: JsonToken,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<(), &'static str> {
+        match self.expecting.step(token)? {
+            Step::Whitespace => {}
+            Step::Key(key) => {
+                self.build(|x| {
+                    x.key(key.clone());
+                    None
+                });
+                let object = match self.stack.last_mut() {
+                    Some(CurrentlyScanning::Object {
+                        consumer: Some(consumer),
+                        missing,
+                        ..
+                    }) => {
+                        missing.retain(|x| *x != key);
+                        Some(*consumer)
+                    }
+                    _ => None,
+                };
+                let consumers = match object {
+                    Some(consumer) => consumers_for_key(consumer, &key),
+                    None => ValueConsumers::default(),
+                };
+                let unknown = object.is_some_and(|x| {
+                    x.denies_unknown_keys()
+                        && consumers.is_empty()
+                        && !x.required_keys().contains(&key)
+                });
+                self.stack.push(CurrentlyScanning::KeyValuePair(consumers));
+                self.path.push(PathSegment::Key(key.clone()));
+                self.expecting = Expecting::ObjectValueIndicator;
+                if unknown {
+                    self.invalid(ValidationError::UnknownKey(key), self.offset)?;
+                }
+            }
+            Step::ObjectValueIndicator => {
+                let consumers = self.value_consumers();
+                if let Some(raw) = consumers.raw {
+                    buffer.start_capture(*raw, self.path.clone()).await;
+                    self.capture = Some(self.stack.len());
+                }
+                self.expecting = Expecting::Value;
+            }
+            Step::NextKey => {
+                self.expecting = Expecting::Key;
+            }
+            Step::NextMember => {
+                if let Some(PathSegment::Index(i)) = self.path.last_mut() {
+                    *i += 1;
+                }
+                self.expecting = Expecting::Value;
+            }
+            Step::ContainerEnd => {
+                self.end_container(buffer).await?;
+            }
+            Step::ObjectStart => {
+                let consumers = self.value_consumers();
+                self.start_building(consumers);
+                self.build(|x| {
+                    x.start_object();
+                    None
+                });
+                if let Some(PrimitiveTarget::Typed(consumer)) = consumers.primitive {
+                    self.type_mismatch(consumer.mismatch("object"))?;
+                }
+                let missing = match consumers.object {
+                    Some(x) => x.required_keys().iter().map(String::as_str).collect(),
+                    None => Vec::new(),
+                };
+                self.stack.push(CurrentlyScanning::Object {
+                    consumer: consumers.object,
+                    start: self.offset,
+                    count: 0,
+                    missing,
+                });
+                // The hook is called once the object is on the stack so that it can skip it
+                if let Some(hook) = consumers.object.and_then(|x| x.start_hook()) {
+                    self.call_hook(hook);
+                }
+                self.expecting = Expecting::after_start(true);
+            }
+            Step::ArrayStart => {
+                let consumers = self.value_consumers();
+                self.start_building(consumers);
+                self.build(|x| {
+                    x.start_array();
+                    None
+                });
+                if let Some(PrimitiveTarget::Typed(consumer)) = consumers.primitive {
+                    self.type_mismatch(consumer.mismatch("array"))?;
+                }
+                self.stack.push(CurrentlyScanning::Array {
+                    consumer: consumers.array,
+                    end_hook: consumers.array_end_hook,
+                    start: self.offset,
+                    count: 0,
+                });
+                // The path is still to the array itself for the hook
+                if let Some(hook) = consumers.array_start_hook {
+                    self.call_hook(hook);
+                }
+                self.path.push(PathSegment::Index(0));
+                self.expecting = Expecting::after_start(false);
+            }
+            Step::Primitive(primitive) => {
+                let consumers = self.value_consumers();
+                self.start_building(consumers);
+                match consumers.primitive {
+                    Some(consumer) => self.call_primitive(consumer, primitive)?,
+                    None => self.build(|x| x.value(JsonValue::from(primitive))),
+                }
+                self.end_value(buffer).await;
+            }
+        };
+
+        return Ok(());
+    }
+}
+
+impl Parser {
+    pub fn new(json_path: ObjectConsumer) -> Self {
+        return Self {
+            json_path,
+            skip_unregistered: false,
+            stop_when_complete: false,
+            type_mismatch_handler: None,
+            validation_handler: None,
+            checkpoints: None,
+            root_array_hooks: (None, None),
+            input_offsets: None,
+            consumer_state: None,
+        };
+    }
+
+    /// Skips over values that no consumer is registered for, only checking that their brackets
+    /// match. This is much faster when most of the input is not wanted, but invalid JSON in
+    /// those values is not found, so it is off by default.
+    pub fn skip_unregistered(mut self, skip_unregistered: bool) -> Self {
+        self.skip_unregistered = skip_unregistered;
+        return self;
+    }
+
+    /// Stops parsing once every registered consumer has been called, so the rest of the input
+    /// is not read. The consumers for the members of an array are done when the array ends.
+    pub fn stop_when_complete(mut self, stop_when_complete: bool) -> Self {
+        self.stop_when_complete = stop_when_complete;
+        return self;
+    }
+
+    /// Values that have the wrong type for their typed consumer are passed to the handler
+    /// rather than failing parsing, the typed consumer is not called for them.
+    pub fn on_type_mismatch(mut self, handler: TypeMismatchHandler) -> Self {
+        self.type_mismatch_handler = Some(handler);
+        return self;
+    }
+
+    /// Objects that do not have the required keys of their consumer, or that have unknown keys
+    /// when those are denied, are passed to the handler rather than failing parsing.
+    pub fn on_validation_error(mut self, handler: ValidationHandler) -> Self {
+        self.validation_handler = Some(handler);
+        return self;
+    }
+
+    /// Passes a checkpoint to the handler after the first value that ends once at least
+    /// `interval` bytes have been read since the last one. Parsing can be continued from a
+    /// saved checkpoint with `resume()`.
+    pub fn checkpoint_every(mut self, interval: usize, handler: CheckpointHandler) -> Self {
+        self.checkpoints = Some((interval, handler));
+        return self;
+    }
+
+    /// Called when the root of the document is an array and it starts, before any of its
+    /// members are scanned.
+    pub fn on_root_array_start(mut self, hook: StartHook) -> Self {
+        self.root_array_hooks.0 = Some(hook);
+        return self;
+    }
+
+    /// Called when the root of the document is an array and it ends with the number of members
+    /// that it had.
+    pub fn on_root_array_end(mut self, hook: EndHook) -> Self {
+        self.root_array_hooks.1 = Some(hook);
+        return self;
+    }
+
+    /// Gives the offsets of errors in the input that a decoder turned into the text that is
+    /// parsed, rather than in the text. The offsets passed to consumers and in checkpoints are
+    /// still in the text, so resuming from a checkpoint needs the decoded text.
+    pub fn input_offsets(mut self, offsets: OffsetMap) -> Self {
+        self.input_offsets = Some(offsets);
+        return self;
+    }
+
+    /// Passes the state to every consumer in its `PathContext`, so consumers that are plain
+    /// functions can collect what they are called with into it.
+    pub fn consumer_state(mut self, state: Arc<dyn Any + Send + Sync>) -> Self {
+        self.consumer_state = Some(state);
+        return self;
+    }
+
+    /// The offset in the input of an offset in the text that is parsed
+    fn input_offset(&self, offset: usize) -> usize {
+        return match &self.input_offsets {
+            Some(x) => x.input_offset(offset),
+            None => offset,
+        };
+    }
+
+    fn state(&self) -> ParserState<'_> {
+        let mut state = ParserState::new(&self.json_path);
+        state.skip_unregistered = self.skip_unregistered;
+        state.stop_when_complete = self.stop_when_complete;
+        state.type_mismatch_handler = self.type_mismatch_handler;
+        state.validation_handler = self.validation_handler;
+        state.checkpoints = self.checkpoints;
+        state.root_array_hooks = (
+            self.root_array_hooks.0.as_ref(),
+            self.root_array_hooks.1.as_ref(),
+        );
+        state.consumer_state = self.consumer_state.as_deref();
+        state.input_offsets = self.input_offsets.as_ref();
+        return state;
+    }
+
+    /// Parses the JSON in the buffer calling the consumers as their values are scanned. If a
+    /// consumer stops the parser then the buffer is closed and this returns early.
+    pub async fn parse(self, buffer: &mut Pin<Box<&mut Buffer>>) -> Result<(), ParseError> {
+        let res = self.state().run_with_offset(buffer).await;
+        return res.map_err(|x| ParseError {
+            offset: self.input_offset(x.offset),
+            ..x
+        });
+    }
+
+    /// Parses the input that `write` adds to a buffer as it is added. Once parsing fails or a
+    /// consumer stops it, `write` is dropped so that it does not wait for the buffer to have
+    /// space. An error from `write` is returned before one from parsing, as the parser would
+    /// only see the input end early.
+    pub async fn parse_while_writing<F: Future<Output = Result<(), ParseError>>>(
+        self,
+        write: impl FnOnce(BufferWriter) -> F,
+    ) -> Result<(), ParseError> {
+        let (writer, mut buffer) = Buffer::bounded_channel(BufferLimits::new(WRITE_QUEUE_LIMIT));
+        let buffer_pinned = &mut Box::pin(&mut buffer);
+        let (written, parsed) = {
+            let write = write(writer);
+            let parse = self.parse(buffer_pinned);
+            tokio::pin!(write, parse);
+
+            let written = tokio::select! {
+                parsed = &mut parse => return parsed,
+                written = &mut write => written,
+            };
+            (written, parse.await)
+        };
+
+        return match written {
+            // The buffer is closed when a consumer stops the parser, which fails the writer
+            Err(e) if !buffer_pinned.is_closed() => Err(e),
+            _ => parsed,
+        };
+    }
+
+    /// Parses the JSON in the buffer like `parse()`, but any error has the line of the input that
+    /// it is on, the path to the value that it is in and a hint about what may be wrong, which
+    /// are shown when it is displayed.
+    pub async fn parse_with_diagnostic(
+        self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<(), Diagnostic> {
+        let res = self.state().run_with_diagnostic(buffer).await;
+        return res.map_err(|x| Diagnostic {
+            offset: self.input_offset(x.offset),
+            ..x
+        });
+    }
+
+    /// Parses the JSON in the buffer without stopping at errors. After an error the parser
+    /// skips to the next comma or closing bracket of the object or array that the error was in
+    /// and carries on, so consumers are still called for the valid parts of the input. Every
+    /// error is returned in the order they were found, which is empty if the input is valid.
+    pub async fn parse_recovering(self, buffer: &mut Pin<Box<&mut Buffer>>) -> Vec<ParseError> {
+        let mut state = self.state();
+        state.diagnostics = Some(Vec::new());
+        let res = state.run_with_offset(buffer).await;
+
+        // The offsets of the errors that were recovered from are already in the input
+        let mut diagnostics = state.diagnostics.take().unwrap_or_default();
+        if let Err(x) = res {
+            diagnostics.push(ParseError {
+                offset: self.input_offset(x.offset),
+                ..x
+            });
+        }
+        return diagnostics;
+    }
+
+    /// Continues parsing from a checkpoint, the buffer has to have the text from the offset of
+    /// the checkpoint onwards and the consumers have to be those of the parser that took it.
+    /// As with `parse()`, errors are at offsets in the input when `input_offsets()` is given
+    /// the map of how the text was decoded.
+    /// Which consumers have been called is not saved in checkpoints, so `stop_when_complete`
+    /// only stops once each consumer has been called again after resuming.
+    pub async fn resume(
+        self,
+        checkpoint: &Checkpoint,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<(), ParseError> {
+        let mut state = self.state();
+        if let Err(message) = state.restore(checkpoint) {
+            return Err(ParseError {
+                message,
+                offset: self.input_offset(checkpoint.offset()),
+            });
+        }
+
+        buffer.set_offset(checkpoint.offset()).await;
+        let res = state.run_with_offset(buffer).await;
+        return res.map_err(|x| ParseError {
+            offset: self.input_offset(x.offset),
+            ..x
+        });
+    }
+}
+
+/// Parses the whole of the JSON in the buffer into a `JsonValue`.
+pub async fn parse_to_value(buffer: &mut Pin<Box<&mut Buffer>>) -> Result<JsonValue, ParseError> {
+    let json_path = ObjectConsumer::new();
+    let mut state = ParserState::new(&json_path);
+    state.building = Some((ValueBuilder::new(), BuildingFor::Document));
+    state.run_with_offset(buffer).await?;
+
+    return match state.document {
+        Some(x) => Ok(x),
+        None => Err(ParseError {
+            message: "Unexpected end of input",
+            offset: buffer.offset().await,
+        }),
+    };
+}
+
+#[cfg(test)]
+pub(crate) mod test_parser {
+    use super::*;
+    use crate::parser::{
+        base64::Base64Alphabet,
+        diagnostic::Diagnostic,
+        json_path::{KeyPattern, RawFragment, TypedConsumer},
+        lexer::tokens::number_token::NumberToken,
+    };
+    use regex::Regex;
+    use std::{
+        borrow::BorrowMut,
+        sync::{Arc, Mutex},
+    };
+
+    /// A buffer that has had all of the chunks added to it and has ended
+    pub(crate) async fn buffer_with_chunks(chunks: &[&str]) -> Buffer {
+        let mut buffer = Buffer::new();
+        for chunk in chunks {
+            buffer
+                .add_data(chunk.chars().collect::<Vec<char>>())
+                .await
+                .unwrap();
+        }
+        buffer.eof().await;
+        return buffer;
+    }
+
+    /// More rows than the buffer queues, so adding them waits for the parser
+    pub(crate) const LARGE_ROWS: i64 = 60_000;
+
+    /// An object with a `rows` array of `n` objects. The rows are long enough that a large
+    /// input is read in many chunks.
+    pub(crate) fn rows(n: i64) -> String {
+        let rows: Vec<String> = (0..n)
+            .map(|i| format!(r#"{{"id": {}, "name": "row {}"}}"#, i, i))
+            .collect();
+        return format!(r#"{{"rows": [{}]}}"#, rows.join(", "));
+    }
+
+    /// Calls the consumer with the `id` of each of the rows
+    pub(crate) fn rows_consumer(
+        consumer: fn(i64, &PathContext) -> ConsumerAction,
+    ) -> ObjectConsumer {
+        return ObjectConsumer::new()
+            .array(
+                "rows".to_string(),
+                UnknownConsumer::ObjectConsumer(
+                    ObjectConsumer::new()
+                        .i64("id".to_string(), consumer)
+                        .clone(),
+                ),
+            )
+            .clone();
+    }
+
+    async fn parse_chunks(json_path: ObjectConsumer, chunks: &[&str]) -> Result<(), ParseError> {
+        let mut buffer = buffer_with_chunks(chunks).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        return Parser::new(json_path).parse(buffer_pinned).await;
+    }
+
+    #[tokio::test]
+    async fn test_parse_calls_primitive_consumers() {
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .primitive("id".to_string(), |x, _| match x {
+                    JsonPrimitive::Number(NumberToken::Integer(i)) => {
+                        IDS.lock().unwrap().push(i);
+                        ConsumerAction::Continue
+                    }
+                    _ => panic!("Expected an integer"),
+                })
+                .object(
+                    "owner".to_string(),
+                    ObjectConsumer::new().primitive("id".to_string(), |x, _| match x {
+                        JsonPrimitive::Number(NumberToken::Integer(i)) => {
+                            IDS.lock().unwrap().push(i);
+                            ConsumerAction::Continue
+                        }
+                        _ => panic!("Expected an integer"),
+                    }),
+                )
+                .clone(),
+            &[r#"{"name": "a", "owner": {"id": 29, "x": [1, {"id": 3}]}, "id": 19}"#],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*IDS.lock().unwrap(), vec![29, 19]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_calls_array_consumers() {
+        static NAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .array(
+                    "friends".to_string(),
+                    UnknownConsumer::ObjectConsumer(
+                        ObjectConsumer::new()
+                            .primitive("name".to_string(), |x, _| match x {
+                                JsonPrimitive::String(s) => {
+                                    NAMES.lock().unwrap().push(s.as_string());
+                                    ConsumerAction::Continue
+                                }
+                                _ => panic!("Expected a string"),
+                            })
+                            .clone(),
+                    ),
+                )
+                .clone(),
+            &[r#"{"friends": [{"name": "a"}, {"name": "b", "age": 9}], "name": "c"}"#],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*NAMES.lock().unwrap(), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_invalid_json() {
+        for json in [
+            r#"{"a" 1}"#,
+            r#"{"a": 1,}"#,
+            r#"[1 ,, 2]"#,
+            r#"{"a": 1"#,
+            "{} {}",
+        ] {
+            let res = parse_chunks(ObjectConsumer::new(), &[json]).await;
+            assert!(res.is_err(), "{} should not parse", json);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_raw_consumer() {
+        static RAW: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .raw("payload".to_string(), |x, _| {
+                    match x {
+                        RawFragment::Chunk(s) => RAW.lock().unwrap().push(s.to_string()),
+                        RawFragment::End => RAW.lock().unwrap().push("<end>".to_string()),
+                    }
+                    ConsumerAction::Continue
+                })
+                .clone(),
+            &[
+                r#"{"id": 1, "payload":  {"a": [1, 2.5e3, "➽\"x"], "#,
+                r#""b": {}}  , "other": {"payload": 4}}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *RAW.lock().unwrap(),
+            vec![r#"{"a": [1, 2.5e3, "➽\"x"],"#, r#" "b": {}}"#, "<end>"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_raw_consumer_number_over_many_chunks() {
+        static RAW: Mutex<String> = Mutex::new(String::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .raw("n".to_string(), |x, _| {
+                    match x {
+                        RawFragment::Chunk(s) => RAW.lock().unwrap().push_str(s),
+                        RawFragment::End => RAW.lock().unwrap().push('$'),
+                    }
+                    ConsumerAction::Continue
+                })
+                .clone(),
+            &[r#"{"n": 12"#, "34", "9  ", " ", "}"],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*RAW.lock().unwrap(), "12349$");
+    }
+
+    #[tokio::test]
+    async fn test_parse_raw_consumer_invalid_value() {
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .raw("payload".to_string(), |_, _| ConsumerAction::Continue)
+                .clone(),
+            &[r#"{"payload": [1, true false]}"#],
+        )
+        .await;
+
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_value_consumer() {
+        static VALUES: Mutex<Vec<JsonValue>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .value("owner".to_string(), |x, _| {
+                    VALUES.lock().unwrap().push(x);
+                    ConsumerAction::Continue
+                })
+                .array(
+                    "rows".to_string(),
+                    UnknownConsumer::ObjectConsumer(
+                        ObjectConsumer::new()
+                            .value("tags".to_string(), |x, _| {
+                                VALUES.lock().unwrap().push(x);
+                                ConsumerAction::Continue
+                            })
+                            .clone(),
+                    ),
+                )
+                .clone(),
+            &[
+                r#"{"owner": {"name": "a", "id": 1, "x": [null, true, "#,
+                r#"{}]}, "rows": [{"tags": ["b"]}, {"tags": []}]}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        let values = VALUES.lock().unwrap();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0].get("name").unwrap().as_str(), Some("a"));
+        assert_eq!(
+            values[0].get("id"),
+            Some(&JsonValue::Number(NumberToken::Integer(1)))
+        );
+        assert_eq!(
+            values[0].get("x"),
+            Some(&JsonValue::Array(vec![
+                JsonValue::Null,
+                JsonValue::Boolean(true),
+                JsonValue::Object(Default::default())
+            ]))
+        );
+        assert_eq!(
+            values[1],
+            JsonValue::Array(vec![JsonValue::String("b".to_string())])
+        );
+        assert_eq!(values[2], JsonValue::Array(vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_parse_to_value() {
+        let mut buffer =
+            buffer_with_chunks(&[r#"  {"b": [1, 2.5], "a": {"c": fal"#, r#"se}}  "#]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let value = parse_to_value(buffer_pinned).await.unwrap();
+
+        let keys: Vec<&String> = match &value {
+            JsonValue::Object(x) => x.keys().collect(),
+            _ => panic!("Expected an object"),
+        };
+        assert_eq!(keys, vec!["b", "a"]);
+        assert_eq!(
+            value.get("b"),
+            Some(&JsonValue::Array(vec![
+                JsonValue::Number(NumberToken::Integer(1)),
+                JsonValue::Number(NumberToken::Float(2.5))
+            ]))
+        );
+        assert_eq!(
+            value.get("a").unwrap().get("c"),
+            Some(&JsonValue::Boolean(false))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_to_value_primitive_document() {
+        let mut buffer = buffer_with_chunks(&[r#" "abc" "#]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        assert_eq!(
+            parse_to_value(buffer_pinned).await.unwrap(),
+            JsonValue::String("abc".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_to_value_invalid_json() {
+        let mut buffer = buffer_with_chunks(&[r#"{"a": [1, 2}"#]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        assert!(parse_to_value(buffer_pinned).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_skips_unregistered_values() {
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        let json_path = ObjectConsumer::new()
+            .primitive("id".to_string(), |x, _| match x {
+                JsonPrimitive::Number(NumberToken::Integer(i)) => {
+                    IDS.lock().unwrap().push(i);
+                    ConsumerAction::Continue
+                }
+                _ => panic!("Expected an integer"),
+            })
+            .clone();
+        let json = r#"{"a": [1 2, {"b": "}"}], "c": tru, "id": 7}"#;
+
+        let mut buffer = buffer_with_chunks(&[json]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(json_path.clone())
+            .skip_unregistered(true)
+            .parse(buffer_pinned)
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(*IDS.lock().unwrap(), vec![7]);
+
+        let res = parse_chunks(json_path, &[json]).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_rejects_invalid_unregistered_values_by_default() {
+        for json in [r#"{"x": tru}"#, r#"{"x": [1 2]}"#, r#"{"x": {"y" 1}}"#] {
+            let res = parse_chunks(ObjectConsumer::new(), &[json]).await;
+            assert!(res.is_err(), "{}", json);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_skip_subtree() {
+        static NAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .array(
+                    "rows".to_string(),
+                    UnknownConsumer::ObjectConsumer(
+                        ObjectConsumer::new()
+                            .primitive("hidden".to_string(), |x, _| match x {
+                                JsonPrimitive::Boolean(true) => ConsumerAction::SkipSubtree,
+                                _ => ConsumerAction::Continue,
+                            })
+                            .primitive("name".to_string(), |x, _| match x {
+                                JsonPrimitive::String(s) => {
+                                    NAMES.lock().unwrap().push(s.as_string());
+                                    ConsumerAction::Continue
+                                }
+                                _ => panic!("Expected a string"),
+                            })
+                            .clone(),
+                    ),
+                )
+                .clone(),
+            &[concat!(
+                r#"{"rows": [{"hidden": false, "name": "a"}, "#,
+                r#"{"hidden": true, "name": "b", "x": [{"}": 1}]}, "#,
+                r#"{"name": "c", "hidden": true, "name": "d"}, {"name": "e"}]}"#
+            )],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*NAMES.lock().unwrap(), vec!["a", "c", "e"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_consumer_stops_parser() {
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        let mut buffer =
+            buffer_with_chunks(&[r#"{"ids": [1, 2, 3, 4], "#, "this is not json"]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(
+            ObjectConsumer::new()
+                .array(
+                    "ids".to_string(),
+                    UnknownConsumer::PrimitiveConsumer(|x, _| match x {
+                        JsonPrimitive::Number(NumberToken::Integer(i)) => {
+                            IDS.lock().unwrap().push(i);
+                            if i == 2 {
+                                ConsumerAction::Stop
+                            } else {
+                                ConsumerAction::Continue
+                            }
+                        }
+                        _ => panic!("Expected an integer"),
+                    }),
+                )
+                .clone(),
+        )
+        .parse(buffer_pinned)
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*IDS.lock().unwrap(), vec![1, 2]);
+        assert!(buffer_pinned.is_eof().await);
+    }
+
+    #[tokio::test]
+    async fn test_parse_stop_when_complete() {
+        static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let json_path = ObjectConsumer::new()
+            .object(
+                "metadata".to_string(),
+                ObjectConsumer::new().primitive("version".to_string(), |_, _| {
+                    CALLS.lock().unwrap().push("version".to_string());
+                    ConsumerAction::Continue
+                }),
+            )
+            .array(
+                "tags".to_string(),
+                UnknownConsumer::PrimitiveConsumer(|_, _| {
+                    CALLS.lock().unwrap().push("tag".to_string());
+                    ConsumerAction::Continue
+                }),
+            )
+            .value("owner".to_string(), |_, _| {
+                CALLS.lock().unwrap().push("owner".to_string());
+                ConsumerAction::Continue
+            })
+            .clone();
+
+        let mut buffer = buffer_with_chunks(&[
+            r#"{"tags": ["a", "b"], "metadata": {"version": 3}, "#,
+            r#""owner": {"id": 1}, "#,
+            r#""rest": [1, 2, 3"#,
+        ])
+        .await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(json_path)
+            .stop_when_complete(true)
+            .parse(buffer_pinned)
+            .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            vec!["tag", "tag", "version", "owner"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_stop_when_complete_with_many_consumers_for_a_key() {
+        static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        // Only one of the consumers for `id` is called for each type of value
+        let json_path = ObjectConsumer::new()
+            .i64("id".to_string(), |x, _| {
+                CALLS.lock().unwrap().push(format!("i64 {}", x));
+                ConsumerAction::Continue
+            })
+            .object("id".to_string(), &ObjectConsumer::new())
+            .array(
+                "id".to_string(),
+                UnknownConsumer::PrimitiveConsumer(|_, _| ConsumerAction::Continue),
+            )
+            .primitive("name".to_string(), |_, _| {
+                CALLS.lock().unwrap().push("name".to_string());
+                ConsumerAction::Continue
+            })
+            .clone();
+
+        let mut buffer =
+            buffer_with_chunks(&[r#"{"id": 1, "name": "a", "#, r#""rest": [1, 2, 3"#]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(json_path)
+            .stop_when_complete(true)
+            .parse(buffer_pinned)
+            .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*CALLS.lock().unwrap(), vec!["i64 1", "name"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_stop_when_complete_with_a_shared_object_consumer() {
+        static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        // `user` counts once whether its value is an object or a number
+        let user = ObjectConsumer::new()
+            .primitive("name".to_string(), |_, _| {
+                CALLS.lock().unwrap().push("name".to_string());
+                ConsumerAction::Continue
+            })
+            .primitive("age".to_string(), |_, _| {
+                CALLS.lock().unwrap().push("age".to_string());
+                ConsumerAction::Continue
+            })
+            .clone();
+        let json_path = ObjectConsumer::new()
+            .primitive("user".to_string(), |_, _| {
+                CALLS.lock().unwrap().push("user".to_string());
+                ConsumerAction::Continue
+            })
+            .object("user".to_string(), &user)
+            .primitive("id".to_string(), |_, _| {
+                CALLS.lock().unwrap().push("id".to_string());
+                ConsumerAction::Continue
+            })
+            .clone();
+        assert_eq!(json_path.required_calls(), 2);
+
+        let mut buffer = buffer_with_chunks(&[
+            r#"{"user": {"name": "a", "age": 2}, "#,
+            r#""id": 1, "rest": [1, 2, 3"#,
+        ])
+        .await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(json_path)
+            .stop_when_complete(true)
+            .parse(buffer_pinned)
+            .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*CALLS.lock().unwrap(), vec!["name", "age", "id"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_borrows_plain_strings() {
+        static STRINGS: Mutex<Vec<(String, bool)>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .array(
+                    "names".to_string(),
+                    UnknownConsumer::PrimitiveConsumer(|x, _| match x {
+                        JsonPrimitive::String(s) => {
+                            let borrowed = s.is_borrowed();
+                            STRINGS.lock().unwrap().push((s.as_string(), borrowed));
+                            ConsumerAction::Continue
+                        }
+                        _ => panic!("Expected a string"),
+                    }),
+                )
+                .clone(),
+            &[r#"{"names": ["plain", "esc\"aped", "spl"#, r#"it", "➽"]}"#],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *STRINGS.lock().unwrap(),
+            vec![
+                ("plain".to_string(), true),
+                ("esc\"aped".to_string(), false),
+                ("split".to_string(), false),
+                ("➽".to_string(), true)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_string_chunk_consumer() {
+        static FRAGMENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .string_chunks("body".to_string(), |x, _| {
+                    FRAGMENTS.lock().unwrap().push(match x {
+                        StringFragment::Chunk(s) => s.to_string(),
+                        StringFragment::End => "<end>".to_string(),
+                    });
+                    ConsumerAction::Continue
+                })
+                .clone(),
+            &[
+                r#"{"id": 1, "body": "Hello ➽"#,
+                r#" wor\nld", "after": "x"}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        let fragments = FRAGMENTS.lock().unwrap();
+        assert_eq!(fragments.last().unwrap(), "<end>");
+        assert_eq!(fragments[..fragments.len() - 1].concat(), "Hello ➽ wor\nld");
+    }
+
+    #[tokio::test]
+    async fn test_parse_string_chunk_consumer_stops_parser() {
+        static FRAGMENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .string_chunks("body".to_string(), |x, _| match x {
+                    StringFragment::Chunk(s) => {
+                        FRAGMENTS.lock().unwrap().push(s.to_string());
+                        ConsumerAction::Stop
+                    }
+                    StringFragment::End => panic!("The string should not be finished"),
+                })
+                .clone(),
+            &[r#"{"body": "first"#, r#" second"#, r#" unterminated"#],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*FRAGMENTS.lock().unwrap(), vec!["first"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_base64_consumer() {
+        let file = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+        let url_safe = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .base64("file".to_string(), Base64Alphabet::Standard, file.clone())
+                .base64("id".to_string(), Base64Alphabet::UrlSafe, url_safe.clone())
+                .clone(),
+            &[r#"{"file": "SGVsb"#, r#"G8gd2"#, r#"9ybGQ=", "id": "-_8"}"#],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*file.lock().await, b"Hello world".to_vec());
+        assert_eq!(*url_safe.lock().await, vec![0xfb, 0xff]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_base64_consumer_invalid() {
+        let file = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .base64("file".to_string(), Base64Alphabet::Standard, file.clone())
+                .clone(),
+            &[r#"{"id": 1, "file": "SGVs"#, r#"b*8="}"#],
+        )
+        .await;
+
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Invalid base64 char",
+                offset: 24
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_error_offset() {
+        let res = parse_chunks(ObjectConsumer::new(), &[r#"{"a": 1,"#, r#" "b" 2}"#]).await;
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Unexpected token",
+                offset: 14
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_wildcard_consumers() {
+        static USERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        static FLAGS: Mutex<Vec<(String, bool)>> = Mutex::new(Vec::new());
+        static OTHER: Mutex<Vec<(String, JsonValue)>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .primitive("version".to_string(), |_, _| ConsumerAction::Continue)
+                .matching(
+                    KeyPattern::Regex(Regex::new("^u[0-9]+$").unwrap()),
+                    KeyedConsumer::ObjectConsumer(
+                        ObjectConsumer::new()
+                            .primitive("name".to_string(), |x, _| match x {
+                                JsonPrimitive::String(s) => {
+                                    USERS.lock().unwrap().push(s.as_string());
+                                    ConsumerAction::Continue
+                                }
+                                _ => panic!("Expected a string"),
+                            })
+                            .clone(),
+                    ),
+                )
+                .matching(
+                    KeyPattern::Prefix("is_".to_string()),
+                    KeyedConsumer::PrimitiveConsumer(|key, x, _| match x {
+                        JsonPrimitive::Boolean(b) => {
+                            FLAGS.lock().unwrap().push((key.to_string(), b));
+                            ConsumerAction::Continue
+                        }
+                        _ => panic!("Expected a boolean"),
+                    }),
+                )
+                .fallback(KeyedConsumer::ValueConsumer(|key, x, _| {
+                    OTHER.lock().unwrap().push((key.to_string(), x));
+                    ConsumerAction::Continue
+                }))
+                .clone(),
+            &[
+                r#"{"version": 2, "u123": {"name": "a"}, "is_admin": true, "#,
+                r#""u456": {"name": "b"}, "is_bot": false, "uX": [1], "extra": null}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*USERS.lock().unwrap(), vec!["a", "b"]);
+        assert_eq!(
+            *FLAGS.lock().unwrap(),
+            vec![
+                ("is_admin".to_string(), true),
+                ("is_bot".to_string(), false)
+            ]
+        );
+        assert_eq!(
+            *OTHER.lock().unwrap(),
+            vec![
+                (
+                    "uX".to_string(),
+                    JsonValue::Array(vec![JsonValue::Number(NumberToken::Integer(1))])
+                ),
+                ("extra".to_string(), JsonValue::Null)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_passes_path_context() {
+        static CALLS: Mutex<Vec<(String, usize, usize)>> = Mutex::new(Vec::new());
+
+        fn record(_: JsonPrimitive, context: &PathContext) -> ConsumerAction {
+            CALLS
+                .lock()
+                .unwrap()
+                .push((context.to_string(), context.depth(), context.offset()));
+            return ConsumerAction::Continue;
+        }
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .primitive("id".to_string(), record)
+                .object(
+                    "owner".to_string(),
+                    ObjectConsumer::new().primitive("id".to_string(), record),
+                )
+                .array(
+                    "friends".to_string(),
+                    UnknownConsumer::ObjectConsumer(
+                        ObjectConsumer::new()
+                            .primitive("id".to_string(), record)
+                            .clone(),
+                    ),
+                )
+                .clone(),
+            &[
+                r#"{"id": 1, "skipped": [1, 2], "owner": {"id": "a"}, "#,
+                r#""friends": [{"id": 2}, {}, {"id": 3}]}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            vec![
+                ("$.id".to_string(), 1, 7),
+                ("$.owner.id".to_string(), 2, 45),
+                ("$.friends[0].id".to_string(), 3, 70),
+                ("$.friends[2].id".to_string(), 3, 85),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_passes_path_context_to_raw_and_value_consumers() {
+        static CALLS: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .raw("raw".to_string(), |x, context| {
+                    if x == RawFragment::End {
+                        CALLS
+                            .lock()
+                            .unwrap()
+                            .push((context.to_string(), context.offset()));
+                    }
+                    ConsumerAction::Continue
+                })
+                .value("first name".to_string(), |_, context| {
+                    CALLS
+                        .lock()
+                        .unwrap()
+                        .push((context.to_string(), context.offset()));
+                    ConsumerAction::Continue
+                })
+                .clone(),
+            &[r#"{"raw":  [1], "first name": {"a": [true]}}"#],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            vec![
+                ("$.raw".to_string(), 9),
+                ("$[\"first name\"]".to_string(), 28),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_start_and_end_hooks() {
+        static ROW: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        static ROWS: Mutex<Vec<(Vec<String>, usize, String)>> = Mutex::new(Vec::new());
+        static EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        fn add_to_row(x: JsonPrimitive, _: &PathContext) -> ConsumerAction {
+            if let JsonPrimitive::String(s) = x {
+                ROW.lock().unwrap().push(s.as_string());
+            }
+            return ConsumerAction::Continue;
+        }
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .on_start(|context| {
+                    EVENTS.lock().unwrap().push(format!("start {}", context));
+                    ConsumerAction::Continue
+                })
+                .on_end(|count, context| {
+                    EVENTS
+                        .lock()
+                        .unwrap()
+                        .push(format!("end {} {}", context, count));
+                    ConsumerAction::Continue
+                })
+                .on_array_start("rows".to_string(), |context| {
+                    EVENTS.lock().unwrap().push(format!("start {}", context));
+                    ConsumerAction::Continue
+                })
+                .on_array_end("rows".to_string(), |count, context| {
+                    EVENTS
+                        .lock()
+                        .unwrap()
+                        .push(format!("end {} {}", context, count));
+                    ConsumerAction::Continue
+                })
+                .on_array_end("empty".to_string(), |count, _| {
+                    EVENTS.lock().unwrap().push(format!("empty {}", count));
+                    ConsumerAction::Continue
+                })
+                .array(
+                    "rows".to_string(),
+                    UnknownConsumer::ObjectConsumer(
+                        ObjectConsumer::new()
+                            .primitive("a".to_string(), add_to_row)
+                            .primitive("b".to_string(), add_to_row)
+                            .on_start(|_| {
+                                ROW.lock().unwrap().clear();
+                                ConsumerAction::Continue
+                            })
+                            .on_end(|count, context| {
+                                let row = ROW.lock().unwrap().clone();
+                                ROWS.lock().unwrap().push((row, count, context.to_string()));
+                                ConsumerAction::Continue
+                            })
+                            .clone(),
+                    ),
+                )
+                .clone(),
+            &[
+                r#"{"rows": [{"a": "1", "b": "2", "c": 3}, {"b": "4"}], "empty": [], "#,
+                r#""other": [1, 2]}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *ROWS.lock().unwrap(),
+            vec![
+                (
+                    vec!["1".to_string(), "2".to_string()],
+                    3,
+                    "$.rows[0]".to_string()
+                ),
+                (vec!["4".to_string()], 1, "$.rows[1]".to_string())
+            ]
+        );
+        assert_eq!(
+            *EVENTS.lock().unwrap(),
+            vec![
+                "start $",
+                "start $.rows",
+                "end $.rows 2",
+                "empty 0",
+                "end $ 3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_passes_consumer_state() {
+        let ids = Arc::new(Mutex::new(Vec::<String>::new()));
+        let mut buffer = buffer_with_chunks(&[r#"{"ids": [1, 2]}"#]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(
+            ObjectConsumer::new()
+                .array(
+                    "ids".to_string(),
+                    UnknownConsumer::PrimitiveConsumer(|x, context| {
+                        let ids = context.state::<Mutex<Vec<String>>>().unwrap();
+                        ids.lock().unwrap().push(format!("{} {:?}", context, x));
+                        ConsumerAction::Continue
+                    }),
+                )
+                .clone(),
+        )
+        .consumer_state(ids.clone())
+        .parse(buffer_pinned)
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *ids.lock().unwrap(),
+            vec!["$.ids[0] Number(Integer(1))", "$.ids[1] Number(Integer(2))"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_start_hooks_skip_their_container() {
+        static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        fn record(x: JsonPrimitive, context: &PathContext) -> ConsumerAction {
+            CALLS.lock().unwrap().push(format!("{} {:?}", context, x));
+            return ConsumerAction::Continue;
+        }
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .object(
+                    "meta".to_string(),
+                    ObjectConsumer::new()
+                        .primitive("x".to_string(), record)
+                        .on_start(|_| ConsumerAction::SkipSubtree),
+                )
+                .array(
+                    "items".to_string(),
+                    UnknownConsumer::PrimitiveConsumer(record),
+                )
+                .on_array_start("items".to_string(), |_| ConsumerAction::SkipSubtree)
+                .primitive("after".to_string(), record)
+                .clone(),
+            &[r#"{"meta": {"x": 1}, "items": [2, 3], "after": 4}"#],
+        )
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(*CALLS.lock().unwrap(), vec!["$.after Number(Integer(4))"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_hooks_for_nested_and_root_arrays() {
+        static EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        fn start(context: &PathContext) -> ConsumerAction {
+            EVENTS.lock().unwrap().push(format!("start {}", context));
+            return ConsumerAction::Continue;
+        }
+
+        fn end(count: usize, context: &PathContext) -> ConsumerAction {
+            EVENTS
+                .lock()
+                .unwrap()
+                .push(format!("end {} {}", context, count));
+            return ConsumerAction::Continue;
+        }
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .array(
+                    "grid".to_string(),
+                    UnknownConsumer::ArrayConsumer(
+                        Box::new(UnknownConsumer::PrimitiveConsumer(|_, _| {
+                            ConsumerAction::Continue
+                        })),
+                        Some(start),
+                        Some(end),
+                    ),
+                )
+                .clone(),
+            &[r#"{"grid": [[1, 2], [3]]}"#],
+        )
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(
+            *EVENTS.lock().unwrap(),
+            vec![
+                "start $.grid[0]",
+                "end $.grid[0] 2",
+                "start $.grid[1]",
+                "end $.grid[1] 1"
+            ]
+        );
+
+        EVENTS.lock().unwrap().clear();
+        let mut buffer = buffer_with_chunks(&[r#"[{"a": 1}, [2], 3]"#]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(ObjectConsumer::new())
+            .on_root_array_start(start)
+            .on_root_array_end(end)
+            .parse(buffer_pinned)
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(*EVENTS.lock().unwrap(), vec!["start $", "end $ 3"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_nested_array_consumers() {
+        static POINTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .array(
+                    "coordinates".to_string(),
+                    UnknownConsumer::ArrayConsumer(
+                        Box::new(UnknownConsumer::ArrayConsumer(
+                            Box::new(UnknownConsumer::PrimitiveConsumer(|x, context| {
+                                if let JsonPrimitive::Number(NumberToken::Integer(i)) = x {
+                                    POINTS.lock().unwrap().push(format!("{} {}", context, i));
+                                }
+                                ConsumerAction::Continue
+                            })),
+                            None,
+                            None,
+                        )),
+                        None,
+                        None,
+                    ),
+                )
+                .clone(),
+            &[
+                r#"{"type": "Polygon", "coordinates": [[[1, 2], [3, 4]], "#,
+                r#"[[5, 6]]]}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *POINTS.lock().unwrap(),
+            vec![
+                "$.coordinates[0][0][0] 1",
+                "$.coordinates[0][0][1] 2",
+                "$.coordinates[0][1][0] 3",
+                "$.coordinates[0][1][1] 4",
+                "$.coordinates[1][0][0] 5",
+                "$.coordinates[1][0][1] 6",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_array_consumers_by_type_and_index() {
+        static MEMBERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        fn record(x: JsonPrimitive, context: &PathContext) -> ConsumerAction {
+            let value = match x {
+                JsonPrimitive::String(s) => s.as_string(),
+                JsonPrimitive::Number(NumberToken::Integer(i)) => i.to_string(),
+                _ => panic!("Expected a string or an integer"),
+            };
+            MEMBERS
+                .lock()
+                .unwrap()
+                .push(format!("{} {:?}", context, value));
+            return ConsumerAction::Continue;
+        }
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .array(
+                    "mixed".to_string(),
+                    UnknownConsumer::ByType(Box::new(
+                        TypedConsumer::new()
+                            .primitive(record)
+                            .object(ObjectConsumer::new().primitive("id".to_string(), record))
+                            .array(UnknownConsumer::PrimitiveConsumer(record))
+                            .clone(),
+                    )),
+                )
+                .array(
+                    "row".to_string(),
+                    UnknownConsumer::Tuple(
+                        vec![
+                            UnknownConsumer::PrimitiveConsumer(record),
+                            UnknownConsumer::ObjectConsumer(
+                                ObjectConsumer::new()
+                                    .primitive("name".to_string(), record)
+                                    .clone(),
+                            ),
+                        ],
+                        Some(Box::new(UnknownConsumer::ArrayConsumer(
+                            Box::new(UnknownConsumer::PrimitiveConsumer(record)),
+                            None,
+                            None,
+                        ))),
+                    ),
+                )
+                .array(
+                    "pair".to_string(),
+                    UnknownConsumer::Tuple(vec![UnknownConsumer::PrimitiveConsumer(record)], None),
+                )
+                .clone(),
+            &[
+                r#"{"mixed": [1, {"id": 2, "x": 0}, [3, 4], "a"], "#,
+                r#""row": [5, {"name": "b"}, [6], [7]], "pair": [8, 9, {"c": 10}]}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *MEMBERS.lock().unwrap(),
+            vec![
+                r#"$.mixed[0] "1""#,
+                r#"$.mixed[1].id "2""#,
+                r#"$.mixed[2][0] "3""#,
+                r#"$.mixed[2][1] "4""#,
+                r#"$.mixed[3] "a""#,
+                r#"$.row[0] "5""#,
+                r#"$.row[1].name "b""#,
+                r#"$.row[2][0] "6""#,
+                r#"$.row[3][0] "7""#,
+                r#"$.pair[0] "8""#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_typed_consumers() {
+        static VALUES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .string("created".to_string(), |x, _| {
+                    VALUES.lock().unwrap().push(format!("string {}", x));
+                    ConsumerAction::Continue
+                })
+                .i64("id".to_string(), |x, _| {
+                    VALUES.lock().unwrap().push(format!("i64 {}", x));
+                    ConsumerAction::Continue
+                })
+                .f64("score".to_string(), |x, _| {
+                    VALUES.lock().unwrap().push(format!("f64 {}", x));
+                    ConsumerAction::Continue
+                })
+                .f64("ratio".to_string(), |x, _| {
+                    VALUES.lock().unwrap().push(format!("f64 {}", x));
+                    ConsumerAction::Continue
+                })
+                .bool("active".to_string(), |x, _| {
+                    VALUES.lock().unwrap().push(format!("bool {}", x));
+                    ConsumerAction::Continue
+                })
+                .nullable_string("a".to_string(), |x, _| {
+                    VALUES.lock().unwrap().push(format!("nullable {:?}", x));
+                    ConsumerAction::Continue
+                })
+                .nullable_string("b".to_string(), |x, _| {
+                    VALUES.lock().unwrap().push(format!("nullable {:?}", x));
+                    ConsumerAction::Continue
+                })
+                .clone(),
+            &[
+                r#"{"created": "2024-01-01", "id": 7, "score": 3, "ratio": 0.5, "#,
+                r#""active": true, "a": null, "b": "x\"y"}"#,
+            ],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *VALUES.lock().unwrap(),
+            vec![
+                "string 2024-01-01",
+                "i64 7",
+                "f64 3",
+                "f64 0.5",
+                "bool true",
+                "nullable None",
+                r#"nullable Some("x\"y")"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_typed_consumer_mismatch() {
+        let json_path = ObjectConsumer::new()
+            .string("created".to_string(), |_, _| panic!("Not a string"))
+            .clone();
+
+        let res = parse_chunks(json_path.clone(), &[r#"{"id": 1, "created": 1700000000}"#]).await;
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Expected a string",
+                offset: 21
+            })
+        );
+
+        let res = parse_chunks(json_path, &[r#"{"created": {"seconds": 1}}"#]).await;
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Expected a string",
+                offset: 12
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_type_mismatch_handler() {
+        static MISMATCHES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        let mut buffer =
+            buffer_with_chunks(&[r#"{"rows": [{"id": 1}, {"id": "2"}, {"id": [3]}, {"id": 4}]}"#])
+                .await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(
+            ObjectConsumer::new()
+                .array(
+                    "rows".to_string(),
+                    UnknownConsumer::ObjectConsumer(
+                        ObjectConsumer::new()
+                            .i64("id".to_string(), |x, _| {
+                                IDS.lock().unwrap().push(x);
+                                ConsumerAction::Continue
+                            })
+                            .clone(),
+                    ),
+                )
+                .clone(),
+        )
+        .on_type_mismatch(|mismatch, context| {
+            MISMATCHES.lock().unwrap().push(format!(
+                "{} {}, found {} at {}",
+                context,
+                mismatch.expected,
+                mismatch.found,
+                context.offset()
+            ));
+            ConsumerAction::Continue
+        })
+        .parse(buffer_pinned)
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(*IDS.lock().unwrap(), vec![1, 4]);
+        assert_eq!(
+            *MISMATCHES.lock().unwrap(),
+            vec![
+                "$.rows[1].id Expected an integer, found string at 28",
+                "$.rows[2].id Expected an integer, found array at 41",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_required_keys() {
+        let json_path = ObjectConsumer::new()
+            .require("id".to_string())
+            .object(
+                "owner".to_string(),
+                ObjectConsumer::new()
+                    .primitive("name".to_string(), |_, _| ConsumerAction::Continue)
+                    .require("name".to_string()),
+            )
+            .clone();
+
+        let res = parse_chunks(json_path.clone(), &[r#"{"id": 1, "owner": {"name": "a"}}"#]).await;
+        assert!(res.is_ok());
+
+        let res = parse_chunks(json_path.clone(), &[r#"{"owner": {"name": "a"}, "id": 1}"#]).await;
+        assert!(res.is_ok());
+
+        let res = parse_chunks(json_path.clone(), &[r#"{"id": 1, "owner": {"nam": "a"}}"#]).await;
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Missing required key",
+                offset: 30
+            })
+        );
+
+        let res = parse_chunks(json_path, &[r#"{"owner": {"name": "a"}}"#]).await;
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Missing required key",
+                offset: 23
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_deny_unknown_keys() {
+        let json_path = ObjectConsumer::new()
+            .primitive("id".to_string(), |_, _| ConsumerAction::Continue)
+            .require("name".to_string())
+            .deny_unknown_keys(true)
+            .clone();
+
+        let res = parse_chunks(json_path.clone(), &[r#"{"id": 1, "name": {"x": 1}}"#]).await;
+        assert!(res.is_ok());
+
+        let res = parse_chunks(json_path, &[r#"{"id": 1, "name": "a", "extra": 2}"#]).await;
+        assert_eq!(
+            res,
+            Err(ParseError {
+                message: "Unknown key",
+                offset: 23
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_validation_handler() {
+        static ERRORS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let mut buffer = buffer_with_chunks(&[
+            r#"{"rows": [{"id": 1, "name": "a"}, {"name": "b", "x": 0}, "#,
+            r#"{"y": 0}]}"#,
+        ])
+        .await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(
+            ObjectConsumer::new()
+                .array(
+                    "rows".to_string(),
+                    UnknownConsumer::ObjectConsumer(
+                        ObjectConsumer::new()
+                            .primitive("name".to_string(), |_, _| ConsumerAction::Continue)
+                            .require("id".to_string())
+                            .require("name".to_string())
+                            .deny_unknown_keys(true)
+                            .clone(),
+                    ),
+                )
+                .clone(),
+        )
+        .on_validation_error(|error, context| {
+            ERRORS
+                .lock()
+                .unwrap()
+                .push(format!("{} {:?} at {}", context, error, context.offset()));
+            ConsumerAction::Continue
+        })
+        .parse(buffer_pinned)
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            *ERRORS.lock().unwrap(),
+            vec![
+                r#"$.rows[1].x UnknownKey("x") at 48"#,
+                r#"$.rows[1] MissingKeys(["id"]) at 34"#,
+                r#"$.rows[2].y UnknownKey("y") at 58"#,
+                r#"$.rows[2] MissingKeys(["id", "name"]) at 57"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_array_value_consumer() {
+        static MEMBERS: Mutex<Vec<(String, JsonValue)>> = Mutex::new(Vec::new());
+
+        let res = parse_chunks(
+            ObjectConsumer::new()
+                .array(
+                    "items".to_string(),
+                    UnknownConsumer::ValueConsumer(|x, context| {
+                        MEMBERS.lock().unwrap().push((context.to_string(), x));
+                        ConsumerAction::Continue
+                    }),
+                )
+                .clone(),
+            &[r#"{"items": [{"id": 1}, "#, r#"[true], null]}"#],
+        )
+        .await;
+
+        assert!(res.is_ok());
+        let mut object = indexmap::IndexMap::new();
+        object.insert("id".to_string(), JsonValue::Number(NumberToken::Integer(1)));
+        assert_eq!(
+            *MEMBERS.lock().unwrap(),
+            vec![
+                ("$.items[0]".to_string(), JsonValue::Object(object)),
+                (
+                    "$.items[1]".to_string(),
+                    JsonValue::Array(vec![JsonValue::Boolean(true)])
+                ),
+                ("$.items[2]".to_string(), JsonValue::Null),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_resume_from_checkpoint() {
+        static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        static CHECKPOINTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let json_path = ObjectConsumer::new()
+            .array(
+                "rows".to_string(),
+                UnknownConsumer::ObjectConsumer(
+                    ObjectConsumer::new()
+                        .i64("id".to_string(), |x, context| {
+                            CALLS.lock().unwrap().push(format!(
+                                "{} {} at {}",
+                                context,
+                                x,
+                                context.offset()
+                            ));
+                            ConsumerAction::Continue
+                        })
+                        .require("name".to_string())
+                        .clone(),
+                ),
+            )
+            .i64("total".to_string(), |x, context| {
+                CALLS
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {} at {}", context, x, context.offset()));
+                ConsumerAction::Continue
+            })
+            .clone();
+        let input = r#"{"rows": [{"id": 1, "name": "a"}, {"id": 2, "name": "b"}], "total": 2}"#;
+
+        let mut buffer = buffer_with_chunks(&[input]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(json_path.clone())
+            .checkpoint_every(16, |checkpoint| {
+                CHECKPOINTS
+                    .lock()
+                    .unwrap()
+                    .push(checkpoint.to_value().to_string());
+            })
+            .parse(buffer_pinned)
+            .await;
+        assert!(res.is_ok());
+        let calls = std::mem::take(&mut *CALLS.lock().unwrap());
+        let checkpoints = CHECKPOINTS.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            vec![
+                "$.rows[0].id 1 at 17",
+                "$.rows[1].id 2 at 41",
+                "$.total 2 at 68"
+            ]
+        );
+        assert_eq!(checkpoints.len(), 3);
+
+        for (i, saved) in checkpoints.iter().enumerate() {
+            let mut buffer = buffer_with_chunks(&[saved]).await;
+            let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+            let checkpoint =
+                Checkpoint::from_value(&parse_to_value(buffer_pinned).await.unwrap()).unwrap();
+
+            let mut buffer = buffer_with_chunks(&[&input[checkpoint.offset()..]]).await;
+            let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+            let res = Parser::new(json_path.clone())
+                .resume(&checkpoint, buffer_pinned)
+                .await;
+            assert!(res.is_ok());
+            assert_eq!(
+                std::mem::take(&mut *CALLS.lock().unwrap()),
+                calls[i + 1..].to_vec()
+            );
+        }
+
+        // The required key that was missing at the checkpoint is still required after resuming
+        let mut buffer = buffer_with_chunks(&[&checkpoints[1]]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let checkpoint =
+            Checkpoint::from_value(&parse_to_value(buffer_pinned).await.unwrap()).unwrap();
+        let mut buffer = buffer_with_chunks(&["}]}"]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        assert_eq!(
+            Parser::new(json_path.clone())
+                .resume(&checkpoint, buffer_pinned)
+                .await,
+            Err(ParseError {
+                message: "Missing required key",
+                offset: 42
+            })
+        );
+
+        let mut buffer = buffer_with_chunks(&["]}"]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let checkpoint = Checkpoint::new(
+            5,
+            vec![PathSegment::Index(0)],
+            vec![CheckpointFrame::KeyValuePair],
+        );
+        assert_eq!(
+            Parser::new(json_path)
+                .resume(&checkpoint, buffer_pinned)
+                .await,
+            Err(ParseError {
+                message: "Invalid checkpoint",
+                offset: 5
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_recovering() {
+        static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let json_path = ObjectConsumer::new()
+            .array(
+                "rows".to_string(),
+                UnknownConsumer::ObjectConsumer(
+                    ObjectConsumer::new()
+                        .i64("id".to_string(), |x, context| {
+                            CALLS.lock().unwrap().push(format!("{} {}", context, x));
+                            ConsumerAction::Continue
+                        })
+                        .require("name".to_string())
+                        .clone(),
+                ),
+            )
+            .i64("total".to_string(), |x, context| {
+                CALLS.lock().unwrap().push(format!("{} {}", context, x));
+                ConsumerAction::Continue
+            })
+            .clone();
+
+        let mut buffer = buffer_with_chunks(&[
+            r#"{"rows": [{"id": 1, "name": "a"}, {"id": 2 "name": "b"}, "#,
+            r#"{"id": "3", "name": "c"}, {"id": 4, "name": "d",}, {"id": nul }], "total": 5,}"#,
+        ])
+        .await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let diagnostics = Parser::new(json_path.clone())
+            .parse_recovering(buffer_pinned)
+            .await;
+
+        let diagnostic = |message, offset| ParseError { message, offset };
+        assert_eq!(
+            diagnostics,
+            vec![
+                diagnostic("Unexpected token", 49),
+                diagnostic("Expected an integer", 64),
+                diagnostic("Unexpected token", 106),
+                diagnostic("Unexpected char", 119),
+                diagnostic("Unexpected token", 135),
+            ]
+        );
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            vec![
+                "$.rows[0].id 1",
+                "$.rows[1].id 2",
+                "$.rows[3].id 4",
+                "$.total 5"
+            ]
+        );
+
+        let mut buffer = buffer_with_chunks(&[r#"{"rows": [{"id": 1, "name": "a"}"#]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        assert_eq!(
+            Parser::new(json_path).parse_recovering(buffer_pinned).await,
+            vec![diagnostic("Unexpected end of input", 32)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_with_diagnostic() {
+        async fn diagnose(input: &str) -> Diagnostic {
+            let mut buffer = buffer_with_chunks(&[input]).await;
+            let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+            return Parser::new(ObjectConsumer::new())
+                .parse_with_diagnostic(buffer_pinned)
+                .await
+                .unwrap_err();
+        }
+
+        let diagnostic =
+            diagnose("{\n  \"rows\": [\n    {\"id\": 1},\n    {\"id\": 2,}\n  ]\n}").await;
+        assert_eq!(
+            diagnostic.to_string(),
+            [
+                "Unexpected token at $.rows[1] (byte 43)",
+                "  --> line 4, column 14",
+                "  |",
+                "4 |     {\"id\": 2,}",
+                "  |              ^",
+                "  = hint: trailing comma",
+            ]
+            .join("\n")
+        );
+
+        for (input, hint) in [
+            ("[1, 2,]", Some("trailing comma")),
+            ("{\"a\" 1}", Some("missing colon after the key")),
+            ("{a: 1}", Some("keys have to be strings in double quotes")),
+            ("{\"a\": 'b'}", Some("strings have to be in double quotes")),
+            ("[{\"a\": \"b, \"c\": 1}]", Some("missing comma")),
+            (
+                "{\"a\": [1, 2}",
+                Some("the bracket does not match the array started at line 1"),
+            ),
+            (
+                "[\n{\"a\": \"bc",
+                Some("unterminated string started at line 2"),
+            ),
+            (
+                "{\"a\": [\n1,\n2\n",
+                Some("unclosed array started at line 1"),
+            ),
+            ("{} []", Some("the root value has already ended")),
+            ("  ", Some("the input is empty")),
+            ("[\"b\\q\"]", None),
+        ] {
+            assert_eq!(diagnose(input).await.hint.as_deref(), hint, "{}", input);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_while_another_task_writes() {
+        static IDS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+        // Many chunks, so the parser reads while the rows are still being written
+        let (mut writer, mut buffer) = Buffer::channel();
+        let producer = tokio::spawn(async move {
+            let input = rows(LARGE_ROWS);
+            for chunk in input.as_bytes().chunks(1000) {
+                tokio::task::yield_now().await;
+                let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+                writer.add_string(chunk).await.unwrap();
+            }
+        });
+
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let res = Parser::new(rows_consumer(|x, _| {
+            IDS.lock().unwrap().push(x);
+            ConsumerAction::Continue
+        }))
+        .parse(buffer_pinned)
+        .await;
+
+        producer.await.unwrap();
+        assert_eq!(res, Ok(()));
+        assert_eq!(*IDS.lock().unwrap(), (0..LARGE_ROWS).collect::<Vec<i64>>());
+    }
 }
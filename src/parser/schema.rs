@@ -0,0 +1,768 @@
+use regex::Regex;
+use std::{collections::HashMap, fmt, pin::Pin};
+
+use super::{
+    buffer::Buffer,
+    json_path::{PathContext, PathSegment},
+    json_value::{JsonValue, ValueBuilder},
+    lexer::{
+        scanners::scan_token,
+        tokens::{number_token::NumberToken, JsonToken},
+    },
+    parser::{next_char_or_eof, Expecting, ParseError, Step},
+};
+
+/// The types that the `type` keyword can allow, as bits so that many can be allowed at once
+const TYPE_NULL: u8 = 1;
+const TYPE_BOOLEAN: u8 = 1 << 1;
+const TYPE_INTEGER: u8 = 1 << 2;
+const TYPE_NUMBER: u8 = 1 << 3;
+const TYPE_STRING: u8 = 1 << 4;
+const TYPE_ARRAY: u8 = 1 << 5;
+const TYPE_OBJECT: u8 = 1 << 6;
+
+/// A compiled subschema, keywords that are not given are `None` or empty.
+#[derive(Clone, Debug, Default)]
+struct SchemaNode {
+    /// The `false` schema, which no value is valid against
+    always_fails: bool,
+    types: Option<u8>,
+    /// From `enum`, or `const` as an enum with one value
+    allowed: Option<Vec<JsonValue>>,
+    /// The most values and keys that are in any of the allowed values, longer objects and
+    /// arrays are not built as they cannot be equal to any of them.
+    allowed_size: usize,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    exclusive_minimum: Option<f64>,
+    exclusive_maximum: Option<f64>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    pattern: Option<Regex>,
+    required: Vec<String>,
+    properties: HashMap<String, SchemaNode>,
+    additional_properties: Option<Box<SchemaNode>>,
+    min_properties: Option<usize>,
+    max_properties: Option<usize>,
+    prefix_items: Vec<SchemaNode>,
+    items: Option<Box<SchemaNode>>,
+    min_items: Option<usize>,
+    max_items: Option<usize>,
+}
+
+/// Counts the values and keys in a value.
+fn value_size(value: &JsonValue) -> usize {
+    return match value {
+        JsonValue::Object(x) => x.values().map(|x| 1 + value_size(x)).sum::<usize>() + 1,
+        JsonValue::Array(x) => x.iter().map(value_size).sum::<usize>() + 1,
+        _ => 1,
+    };
+}
+
+/// Compares values as JSON Schema does, where numbers are equal if they have the same value
+/// even if one is an integer and the other is a float.
+fn values_equal(a: &JsonValue, b: &JsonValue) -> bool {
+    return match (a, b) {
+        (JsonValue::Number(x), JsonValue::Number(y)) => as_f64(x) == as_f64(y),
+        (JsonValue::Array(x), JsonValue::Array(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| values_equal(x, y))
+        }
+        (JsonValue::Object(x), JsonValue::Object(y)) => {
+            x.len() == y.len()
+                && x.iter()
+                    .all(|(key, x)| y.get(key).is_some_and(|y| values_equal(x, y)))
+        }
+        _ => a == b,
+    };
+}
+
+fn as_f64(number: &NumberToken) -> f64 {
+    return match number {
+        NumberToken::Integer(x) => *x as f64,
+        NumberToken::Float(x) => *x,
+    };
+}
+
+fn compile_number(value: &JsonValue) -> Result<f64, &'static str> {
+    return match value {
+        JsonValue::Number(x) => Ok(as_f64(x)),
+        _ => Err("Schema keyword must be a number"),
+    };
+}
+
+fn compile_count(value: &JsonValue) -> Result<usize, &'static str> {
+    return match value {
+        JsonValue::Number(NumberToken::Integer(x)) if *x >= 0 => Ok(*x as usize),
+        _ => Err("Schema keyword must be a non-negative integer"),
+    };
+}
+
+fn compile_type(value: &JsonValue) -> Result<u8, &'static str> {
+    return match value.as_str() {
+        Some("null") => Ok(TYPE_NULL),
+        Some("boolean") => Ok(TYPE_BOOLEAN),
+        Some("integer") => Ok(TYPE_INTEGER),
+        Some("number") => Ok(TYPE_NUMBER),
+        Some("string") => Ok(TYPE_STRING),
+        Some("array") => Ok(TYPE_ARRAY),
+        Some("object") => Ok(TYPE_OBJECT),
+        _ => Err("Unknown type in schema"),
+    };
+}
+
+fn compile_node(schema: &JsonValue) -> Result<SchemaNode, &'static str> {
+    let keywords = match schema {
+        JsonValue::Boolean(true) => return Ok(SchemaNode::default()),
+        JsonValue::Boolean(false) => {
+            return Ok(SchemaNode {
+                always_fails: true,
+                ..Default::default()
+            })
+        }
+        JsonValue::Object(x) => x,
+        _ => return Err("Schema must be an object or a boolean"),
+    };
+
+    let mut node = SchemaNode::default();
+    for (keyword, value) in keywords {
+        match (keyword.as_str(), value) {
+            ("type", JsonValue::Array(x)) => {
+                let mut types = 0;
+                for x in x {
+                    types |= compile_type(x)?;
+                }
+                node.types = Some(types);
+            }
+            ("type", x) => node.types = Some(compile_type(x)?),
+            ("enum", JsonValue::Array(x)) => node.allowed = Some(x.clone()),
+            ("enum", _) => return Err("Schema enum must be an array"),
+            ("const", x) => node.allowed = Some(vec![x.clone()]),
+            ("minimum", x) => node.minimum = Some(compile_number(x)?),
+            ("maximum", x) => node.maximum = Some(compile_number(x)?),
+            ("exclusiveMinimum", x) => node.exclusive_minimum = Some(compile_number(x)?),
+            ("exclusiveMaximum", x) => node.exclusive_maximum = Some(compile_number(x)?),
+            ("minLength", x) => node.min_length = Some(compile_count(x)?),
+            ("maxLength", x) => node.max_length = Some(compile_count(x)?),
+            ("pattern", JsonValue::String(x)) => match Regex::new(x) {
+                Ok(x) => node.pattern = Some(x),
+                Err(_) => return Err("Schema pattern is not a valid regex"),
+            },
+            ("pattern", _) => return Err("Schema pattern must be a string"),
+            ("required", JsonValue::Array(x)) => {
+                for key in x {
+                    match key.as_str() {
+                        Some(key) => node.required.push(key.to_string()),
+                        None => return Err("Schema required must be an array of strings"),
+                    }
+                }
+            }
+            ("required", _) => return Err("Schema required must be an array of strings"),
+            ("properties", JsonValue::Object(x)) => {
+                for (key, x) in x {
+                    node.properties.insert(key.clone(), compile_node(x)?);
+                }
+            }
+            ("properties", _) => return Err("Schema properties must be an object"),
+            ("additionalProperties", x) => {
+                node.additional_properties = Some(Box::new(compile_node(x)?))
+            }
+            ("minProperties", x) => node.min_properties = Some(compile_count(x)?),
+            ("maxProperties", x) => node.max_properties = Some(compile_count(x)?),
+            ("prefixItems", JsonValue::Array(x)) => {
+                node.prefix_items = x.iter().map(compile_node).collect::<Result<_, _>>()?
+            }
+            ("prefixItems", _) => return Err("Schema prefixItems must be an array"),
+            ("items", x) => node.items = Some(Box::new(compile_node(x)?)),
+            ("minItems", x) => node.min_items = Some(compile_count(x)?),
+            ("maxItems", x) => node.max_items = Some(compile_count(x)?),
+            // Annotations and keywords that are not supported
+            _ => {}
+        }
+    }
+
+    node.allowed_size = node
+        .allowed
+        .iter()
+        .flatten()
+        .map(value_size)
+        .max()
+        .unwrap_or(0);
+    return Ok(node);
+}
+
+impl SchemaNode {
+    /// Whether every value is valid against this, so values for it do not have to be scanned
+    fn is_unconstrained(&self) -> bool {
+        return !self.always_fails
+            && self.types.is_none()
+            && self.allowed.is_none()
+            && self.minimum.is_none()
+            && self.maximum.is_none()
+            && self.exclusive_minimum.is_none()
+            && self.exclusive_maximum.is_none()
+            && self.min_length.is_none()
+            && self.max_length.is_none()
+            && self.pattern.is_none()
+            && self.required.is_empty()
+            && self.properties.values().all(|x| x.is_unconstrained())
+            && self
+                .additional_properties
+                .as_ref()
+                .is_none_or(|x| x.is_unconstrained())
+            && self.min_properties.is_none()
+            && self.max_properties.is_none()
+            && self.prefix_items.iter().all(|x| x.is_unconstrained())
+            && self.items.as_ref().is_none_or(|x| x.is_unconstrained())
+            && self.min_items.is_none()
+            && self.max_items.is_none();
+    }
+
+    /// The schema for the value of .key when this is for an object
+    fn property(&self, key: &str) -> Option<&SchemaNode> {
+        return self
+            .properties
+            .get(key)
+            .or(self.additional_properties.as_deref());
+    }
+
+    /// The schema for the member at the index when this is for an array
+    fn item(&self, index: usize) -> Option<&SchemaNode> {
+        return self.prefix_items.get(index).or(self.items.as_deref());
+    }
+}
+
+/// A JSON Schema (draft 2020-12) that documents can be validated against as they are scanned.
+/// The supported keywords are `type`, `enum`, `const`, `minimum`, `maximum`,
+/// `exclusiveMinimum`, `exclusiveMaximum`, `minLength`, `maxLength`, `pattern`, `required`,
+/// `properties`, `additionalProperties`, `minProperties`, `maxProperties`, `prefixItems`,
+/// `items`, `minItems` and `maxItems`, other keywords are ignored.
+#[derive(Clone, Debug)]
+pub struct Schema {
+    root: SchemaNode,
+}
+
+/// A value that is not valid against the schema.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaViolation {
+    pub message: &'static str,
+    /// The keys and array indices from the root of the document to the value, for a missing
+    /// required property this ends with its key.
+    pub path: Vec<PathSegment>,
+    /// The byte offset of the first char of the value, or of the object that a required
+    /// property is missing from
+    pub offset: usize,
+}
+
+/// Formats the violation like `String is too long at $.tags[2] (byte 51)`.
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = PathContext::new(&self.path, self.offset);
+        return write!(f, "{} at {} (byte {})", self.message, context, self.offset);
+    }
+}
+
+/// An object or array that is being validated
+struct Frame<'s> {
+    /// `None` when the values in it are only scanned to build an allowed value
+    schema: Option<&'s SchemaNode>,
+    is_object: bool,
+    start: usize,
+    count: usize,
+    /// The required properties that have not been scanned yet
+    missing: Vec<&'s str>,
+    /// Builds the object or array to compare with the allowed values, and the number of values
+    /// and keys that can be added before it is too large to be one of them
+    building: Option<(ValueBuilder, usize)>,
+    /// The object or array was too large to be one of the allowed values
+    too_large: bool,
+}
+
+struct Validator<'s, F: FnMut(SchemaViolation)> {
+    root: &'s SchemaNode,
+    stack: Vec<Frame<'s>>,
+    path: Vec<PathSegment>,
+    /// The byte offset of the first char of the value that is being scanned
+    offset: usize,
+    expecting: Expecting,
+    report: F,
+}
+
+impl<'s, F: FnMut(SchemaViolation)> Validator<'s, F> {
+    fn violation(&mut self, message: &'static str) {
+        (self.report)(SchemaViolation {
+            message,
+            path: self.path.clone(),
+            offset: self.offset,
+        });
+    }
+
+    /// The schema for the value that is about to be scanned, `None` if any value is valid.
+    fn value_schema(&self) -> Option<&'s SchemaNode> {
+        let schema = match (self.stack.last(), self.path.last()) {
+            (None, _) => Some(self.root),
+            (Some(frame), Some(PathSegment::Key(key))) if frame.is_object => {
+                frame.schema.and_then(|x| x.property(key))
+            }
+            (Some(frame), Some(PathSegment::Index(i))) if !frame.is_object => {
+                frame.schema.and_then(|x| x.item(*i))
+            }
+            _ => None,
+        };
+        return schema.filter(|x| !x.is_unconstrained());
+    }
+
+    /// Adds a value or key to the values that are being built for comparing with the allowed
+    /// values.
+    fn build(&mut self, mut f: impl FnMut(&mut ValueBuilder)) {
+        for frame in self.stack.iter_mut() {
+            match frame.building.as_mut() {
+                Some((_, 0)) => {
+                    frame.building = None;
+                    frame.too_large = true;
+                }
+                Some((builder, remaining)) => {
+                    *remaining -= 1;
+                    f(builder);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn check_type(&mut self, schema: &SchemaNode, types: u8) {
+        if schema.always_fails {
+            self.violation("Value is not allowed");
+        } else if schema.types.is_some_and(|x| x & types == 0) {
+            self.violation("Value has the wrong type");
+        }
+    }
+
+    fn check_primitive(&mut self, schema: &SchemaNode, value: &JsonValue) {
+        let types = match value {
+            JsonValue::Null => TYPE_NULL,
+            JsonValue::Boolean(_) => TYPE_BOOLEAN,
+            JsonValue::Number(x) if as_f64(x).fract() == 0.0 => TYPE_INTEGER | TYPE_NUMBER,
+            JsonValue::Number(_) => TYPE_NUMBER,
+            _ => TYPE_STRING,
+        };
+        self.check_type(schema, types);
+
+        if let Some(allowed) = &schema.allowed {
+            if !allowed.iter().any(|x| values_equal(x, value)) {
+                self.violation("Value is not one of the allowed values");
+            }
+        }
+
+        match value {
+            JsonValue::Number(x) => {
+                let x = as_f64(x);
+                if schema.minimum.is_some_and(|min| x < min)
+                    || schema.exclusive_minimum.is_some_and(|min| x <= min)
+                {
+                    self.violation("Number is less than the minimum");
+                }
+                if schema.maximum.is_some_and(|max| x > max)
+                    || schema.exclusive_maximum.is_some_and(|max| x >= max)
+                {
+                    self.violation("Number is greater than the maximum");
+                }
+            }
+            JsonValue::String(x) => {
+                let length = x.chars().count();
+                if schema.min_length.is_some_and(|min| length < min) {
+                    self.violation("String is too short");
+                }
+                if schema.max_length.is_some_and(|max| length > max) {
+                    self.violation("String is too long");
+                }
+                if schema
+                    .pattern
+                    .as_ref()
+                    .is_some_and(|pattern| !pattern.is_match(x))
+                {
+                    self.violation("String does not match the pattern");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn start_container(&mut self, is_object: bool) {
+        let schema = self.value_schema();
+        self.build(|x| match is_object {
+            true => x.start_object(),
+            false => x.start_array(),
+        });
+
+        let mut building = None;
+        let mut missing = Vec::new();
+        if let Some(schema) = schema {
+            self.check_type(schema, if is_object { TYPE_OBJECT } else { TYPE_ARRAY });
+            if schema.allowed.is_some() {
+                let mut builder = ValueBuilder::new();
+                match is_object {
+                    true => builder.start_object(),
+                    false => builder.start_array(),
+                }
+                building = Some((builder, schema.allowed_size.saturating_sub(1)));
+            }
+            if is_object {
+                missing = schema.required.iter().map(String::as_str).collect();
+            }
+        }
+
+        self.stack.push(Frame {
+            schema,
+            is_object,
+            start: self.offset,
+            count: 0,
+            missing,
+            building,
+            too_large: false,
+        });
+        if !is_object {
+            self.path.push(PathSegment::Index(0));
+        }
+        self.expecting = Expecting::after_start(is_object);
+    }
+
+    fn end_container(&mut self) {
+        let frame = match self.stack.pop() {
+            Some(x) => x,
+            None => return,
+        };
+        if !frame.is_object {
+            self.path.pop();
+        }
+        self.offset = frame.start;
+        // Ending a value does not add to its size
+        for (builder, _) in self.stack.iter_mut().filter_map(|x| x.building.as_mut()) {
+            builder.end();
+        }
+
+        if let Some(schema) = frame.schema {
+            if frame.is_object {
+                for key in frame.missing {
+                    self.path.push(PathSegment::Key(key.to_string()));
+                    self.violation("Missing required property");
+                    self.path.pop();
+                }
+                if schema.min_properties.is_some_and(|min| frame.count < min) {
+                    self.violation("Object has too few properties");
+                }
+                if schema.max_properties.is_some_and(|max| frame.count > max) {
+                    self.violation("Object has too many properties");
+                }
+            } else {
+                if schema.min_items.is_some_and(|min| frame.count < min) {
+                    self.violation("Array has too few items");
+                }
+                if schema.max_items.is_some_and(|max| frame.count > max) {
+                    self.violation("Array has too many items");
+                }
+            }
+
+            if let Some(allowed) = &schema.allowed {
+                let value = frame.building.and_then(|(mut x, _)| x.end());
+                let is_allowed = !frame.too_large
+                    && value.is_some_and(|value| allowed.iter().any(|x| values_equal(x, &value)));
+                if !is_allowed {
+                    self.violation("Value is not one of the allowed values");
+                }
+            }
+        }
+
+        self.end_value();
+    }
+
+    /// Called once a whole value has been scanned, including the end of an object or array.
+    fn end_value(&mut self) {
+        self.expecting = match self.stack.last_mut() {
+            Some(frame) => {
+                if frame.is_object {
+                    self.path.pop();
+                }
+                frame.count += 1;
+                Expecting::after_member(frame.is_object)
+            }
+            None => Expecting::EndOfInput,
+        };
+    }
+
+    fn scan(&mut self, token: JsonToken) -> Result<(), &'static str> {
+        match self.expecting.step(token)? {
+            Step::Whitespace => {}
+            Step::Key(key) => {
+                self.build(|x| x.key(key.clone()));
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.missing.retain(|x| *x != key);
+                }
+                self.path.push(PathSegment::Key(key));
+                self.expecting = Expecting::ObjectValueIndicator;
+            }
+            Step::ObjectValueIndicator => {
+                self.expecting = Expecting::Value;
+            }
+            Step::NextKey => {
+                self.expecting = Expecting::Key;
+            }
+            Step::NextMember => {
+                if let Some(PathSegment::Index(i)) = self.path.last_mut() {
+                    *i += 1;
+                }
+                self.expecting = Expecting::Value;
+            }
+            Step::ContainerEnd => self.end_container(),
+            Step::ObjectStart => self.start_container(true),
+            Step::ArrayStart => self.start_container(false),
+            Step::Primitive(primitive) => {
+                let value = JsonValue::from(primitive);
+                if let Some(schema) = self.value_schema() {
+                    self.check_primitive(schema, &value);
+                }
+                self.build(|x| {
+                    x.value(value.clone());
+                });
+                self.end_value();
+            }
+        }
+
+        return Ok(());
+    }
+
+    async fn run(&mut self, buffer: &mut Pin<Box<&mut Buffer>>) -> Result<(), &'static str> {
+        while let Some(c) = next_char_or_eof(buffer).await? {
+            self.offset = buffer.offset().await - c.len_utf8();
+            // Values that the schema does not constrain are still scanned a token at a time, so
+            // invalid JSON in them fails validation
+            let token = scan_token(c, buffer).await?;
+            self.scan(token)?;
+        }
+
+        if self.expecting != Expecting::EndOfInput {
+            return Err("Unexpected end of input");
+        }
+
+        return Ok(());
+    }
+}
+
+impl Schema {
+    /// Compiles a schema, which has usually been read with `parse_to_value`.
+    pub fn compile(schema: &JsonValue) -> Result<Self, &'static str> {
+        return Ok(Schema {
+            root: compile_node(schema)?,
+        });
+    }
+
+    /// Validates the JSON in the buffer as it is scanned, passing each violation to `report`
+    /// as soon as it is found. Values that the schema does not constrain are scanned without
+    /// being kept, so memory use does not grow with the size of the document. Fails if the
+    /// input is not JSON.
+    pub async fn validate_each(
+        &self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+        report: impl FnMut(SchemaViolation),
+    ) -> Result<(), ParseError> {
+        let mut validator = Validator {
+            root: &self.root,
+            stack: Vec::new(),
+            path: Vec::new(),
+            offset: 0,
+            expecting: Expecting::Value,
+            report,
+        };
+
+        if let Err(message) = validator.run(buffer).await {
+            return Err(ParseError {
+                message,
+                offset: buffer.offset().await,
+            });
+        }
+
+        return Ok(());
+    }
+
+    /// Validates the JSON in the buffer, returning every violation in the order they were
+    /// found.
+    pub async fn validate(
+        &self,
+        buffer: &mut Pin<Box<&mut Buffer>>,
+    ) -> Result<Vec<SchemaViolation>, ParseError> {
+        let mut violations = Vec::new();
+        self.validate_each(buffer, |x| violations.push(x)).await?;
+        return Ok(violations);
+    }
+}
+
+#[cfg(test)]
+mod test_schema {
+    use super::*;
+    use crate::parser::parser::{parse_to_value, test_parser::buffer_with_chunks};
+    use std::borrow::BorrowMut;
+
+    async fn compile(schema: &str) -> Result<Schema, &'static str> {
+        let mut buffer = buffer_with_chunks(&[schema]).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        return Schema::compile(&parse_to_value(buffer_pinned).await.unwrap());
+    }
+
+    async fn validate(schema: &Schema, chunks: &[&str]) -> Result<Vec<String>, ParseError> {
+        let mut buffer = buffer_with_chunks(chunks).await;
+        let buffer_pinned = &mut Box::pin(buffer.borrow_mut());
+        let violations = schema.validate(buffer_pinned).await?;
+        return Ok(violations.iter().map(|x| x.to_string()).collect());
+    }
+
+    #[tokio::test]
+    async fn test_validate_keywords() {
+        let schema = compile(
+            r#"{
+                "type": "object",
+                "required": ["id", "name"],
+                "additionalProperties": false,
+                "properties": {
+                    "id": {"type": "integer", "minimum": 1},
+                    "name": {"type": "string", "minLength": 1, "maxLength": 5},
+                    "score": {"type": ["number", "null"], "exclusiveMaximum": 10},
+                    "tags": {
+                        "type": "array",
+                        "maxItems": 2,
+                        "items": {"type": "string", "pattern": "^[a-z]+$"}
+                    },
+                    "status": {"enum": ["active", "deleted"]},
+                    "point": {"prefixItems": [{"type": "number"}, {"type": "number"}], "items": false},
+                    "meta": {"minProperties": 1},
+                    "extra": true
+                }
+            }"#,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            validate(
+                &schema,
+                &[
+                    r#"{"id": 1, "name": "a", "score": null, "tags": ["x"], "#,
+                    r#""status": "active", "point": [1, 2.5], "extra": [{}]}"#
+                ]
+            )
+            .await,
+            Ok(vec![])
+        );
+
+        assert_eq!(
+            validate(
+                &schema,
+                &[
+                    r#"{"id": 0.5, "score": 10, "tags": ["ok", "Not ok", 3], "#,
+                    r#""status": "gone", "point": [1, "2", 3], "meta": {}, "other": 1}"#,
+                ]
+            )
+            .await,
+            Ok(vec![
+                "Value has the wrong type at $.id (byte 7)".to_string(),
+                "Number is less than the minimum at $.id (byte 7)".to_string(),
+                "Number is greater than the maximum at $.score (byte 21)".to_string(),
+                "String does not match the pattern at $.tags[1] (byte 40)".to_string(),
+                "Value has the wrong type at $.tags[2] (byte 50)".to_string(),
+                "Array has too many items at $.tags (byte 33)".to_string(),
+                "Value is not one of the allowed values at $.status (byte 64)".to_string(),
+                "Value has the wrong type at $.point[1] (byte 85)".to_string(),
+                "Value is not allowed at $.point[2] (byte 90)".to_string(),
+                "Object has too few properties at $.meta (byte 102)".to_string(),
+                "Value is not allowed at $.other (byte 115)".to_string(),
+                "Missing required property at $.name (byte 0)".to_string(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_allowed_objects_and_arrays() {
+        let schema =
+            compile(r#"{"items": {"enum": [{"a": 1, "b": [true]}, [1, 2], "x"]}, "maxItems": 10}"#)
+                .await
+                .unwrap();
+
+        assert_eq!(
+            validate(
+                &schema,
+                &[r#"[{"b": [true], "a": 1.0}, [1, 2], "x", {"a": 1}, [1, 2, 3, 4, 5, 6], [[1, 2]]]"#]
+            )
+            .await,
+            Ok(vec![
+                "Value is not one of the allowed values at $[3] (byte 39)".to_string(),
+                "Value is not one of the allowed values at $[4] (byte 49)".to_string(),
+                "Value is not one of the allowed values at $[5] (byte 69)".to_string(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_scans_unconstrained_values() {
+        let schema = compile(r#"{"properties": {"id": {"type": "string"}}}"#)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            validate(&schema, &[r#"{"data": [{"id": 1}, true], "id": 2}"#]).await,
+            Ok(vec![
+                "Value has the wrong type at $.id (byte 34)".to_string()
+            ])
+        );
+        assert_eq!(
+            validate(&schema, &[r#"{"data": [{"id": 1}, tru], "id": 2}"#]).await,
+            Err(ParseError {
+                message: "Cannot scan boolean",
+                offset: 25
+            })
+        );
+        assert_eq!(
+            validate(&schema, &[r#"{"data": [1, 2}"#]).await,
+            Err(ParseError {
+                message: "Unexpected token",
+                offset: 15
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_invalid_json() {
+        let schema = compile(r#"{"type": "object"}"#).await.unwrap();
+        assert_eq!(
+            validate(&schema, &[r#"{"id": 1,, "a": 2}"#]).await,
+            Err(ParseError {
+                message: "Unexpected token",
+                offset: 10
+            })
+        );
+        assert_eq!(
+            validate(&schema, &[r#"{"id": "a""#]).await,
+            Err(ParseError {
+                message: "Unexpected end of input",
+                offset: 10
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compile_invalid_schema() {
+        assert_eq!(
+            compile(r#"{"type": "text"}"#).await.err(),
+            Some("Unknown type in schema")
+        );
+        assert_eq!(
+            compile(r#"{"pattern": "("}"#).await.err(),
+            Some("Schema pattern is not a valid regex")
+        );
+        assert_eq!(
+            compile(r#"{"items": {"minItems": -1}}"#).await.err(),
+            Some("Schema keyword must be a non-negative integer")
+        );
+        assert_eq!(
+            compile(r#"[]"#).await.err(),
+            Some("Schema must be an object or a boolean")
+        );
+    }
+}